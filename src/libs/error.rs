@@ -0,0 +1,160 @@
+//! Error type shared by the parser and the music model
+use std::fmt;
+use std::io;
+use xml::common::TextPosition;
+use crate::parser::xml_tag::XmlTag;
+
+/// Where in a document an error happened
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct Location {
+    pub path: Vec< String>, // element names from the outermost known tag down
+    pub position: Option<TextPosition>, // position of the innermost tag in the source
+}
+
+impl Location {
+    /// Location of xml_tag itself
+    pub fn of(xml_tag: &XmlTag) -> Self {
+        Location {
+            path: vec![xml_tag.name.local_name.clone()],
+            position: xml_tag.position
+        }
+    }
+
+    /// Location of tag_path searched from xml_tag, e.g. "time/beats" under <attributes>
+    pub fn of_path(xml_tag: &XmlTag, tag_path: &str) -> Self {
+        let mut location = Self::of(xml_tag);
+        location.path.extend(tag_path.split('/').map(|name| name.to_string()));
+        location
+    }
+}
+
+impl fmt::Display for Location {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.path.join("/"))?;
+        if let Some(position) = self.position {
+            // TextPosition counts from 0
+            write!(f, " (line {}, column {})", position.row + 1, position.column + 1)?;
+        }
+        Ok(())
+    }
+}
+
+#[derive(Debug)]
+pub enum MashError {
+    Io(io::Error),
+    /// Unreadable .mxl archive or missing entry in it
+    Zip(zip::result::ZipError),
+    /// Malformed XML reported by xml-rs
+    Xml { message: String, position: TextPosition },
+    /// XML that xml-rs refuses to emit, e.g. an end tag without its start
+    XmlWrite(xml::writer::Error),
+    /// The document ended before its root tag was closed, or had no root at all
+    UnexpectedEof,
+    MissingTag { location: Location },
+    MissingAttribute { name: String, location: Location },
+    MissingText { location: Location },
+    /// A tag or attribute whose value we cannot interpret
+    InvalidValue { value: String, location: Location },
+    /// A tag other than the one expected, e.g. <score-timewise> for <score-partwise>
+    UnexpectedTag { expected: String, location: Location },
+    /// A path query that does not compile, see parser::query
+    InvalidQuery { query: String, reason: String },
+}
+
+pub type MashResult<T> = Result<T, MashError>;
+
+impl MashError {
+    pub fn location(&self) -> Option<&Location> {
+        match self {
+            Self::MissingTag { location }
+            | Self::MissingAttribute { location, .. }
+            | Self::MissingText { location }
+            | Self::InvalidValue { location, .. }
+            | Self::UnexpectedTag { location, .. } => Some(location),
+            _ => None
+        }
+    }
+
+    /// Prefix the element path with the name of xml_tag. Called by every
+    /// try_from_xml_tag on errors from its children, so the path grows as the error
+    /// travels up towards the root
+    pub fn within(mut self, xml_tag: &XmlTag) -> Self {
+        match &mut self {
+            Self::MissingTag { location }
+            | Self::MissingAttribute { location, .. }
+            | Self::MissingText { location }
+            | Self::InvalidValue { location, .. }
+            | Self::UnexpectedTag { location, .. } => {
+                location.path.insert(0, xml_tag.name.local_name.clone());
+                if location.position.is_none() {
+                    location.position = xml_tag.position;
+                }
+            }
+            _ => {}
+        }
+        self
+    }
+}
+
+impl fmt::Display for MashError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::Io(err) => write!(f, "I/O error: {}", err),
+            Self::Zip(err) => write!(f, "invalid .mxl archive: {}", err),
+            Self::Xml { message, position } =>
+                write!(f, "malformed XML at line {}, column {}: {}",
+                       position.row + 1, position.column + 1, message),
+            Self::XmlWrite(err) => write!(f, "cannot write XML: {}", err),
+            Self::UnexpectedEof => write!(f, "document ended before the root tag was closed"),
+            Self::MissingTag { location } => write!(f, "missing tag {}", location),
+            Self::MissingAttribute { name, location } =>
+                write!(f, "missing attribute \"{}\" on {}", name, location),
+            Self::MissingText { location } => write!(f, "missing text content in {}", location),
+            Self::InvalidValue { value, location } =>
+                write!(f, "invalid value \"{}\" in {}", value, location),
+            Self::UnexpectedTag { expected, location } =>
+                write!(f, "expected <{}>, found {}", expected, location),
+            Self::InvalidQuery { query, reason } =>
+                write!(f, "invalid query \"{}\": {}", query, reason),
+        }
+    }
+}
+
+impl std::error::Error for MashError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Self::Io(err) => Some(err),
+            Self::Zip(err) => Some(err),
+            Self::XmlWrite(err) => Some(err),
+            _ => None
+        }
+    }
+}
+
+impl From<io::Error> for MashError {
+    fn from(err: io::Error) -> Self {
+        Self::Io(err)
+    }
+}
+
+impl From<zip::result::ZipError> for MashError {
+    fn from(err: zip::result::ZipError) -> Self {
+        Self::Zip(err)
+    }
+}
+
+impl From<xml::writer::Error> for MashError {
+    fn from(err: xml::writer::Error) -> Self {
+        match err {
+            xml::writer::Error::Io(err) => Self::Io(err),
+            other => Self::XmlWrite(other)
+        }
+    }
+}
+
+impl From<xml::reader::Error> for MashError {
+    fn from(err: xml::reader::Error) -> Self {
+        use xml::common::Position;
+        Self::Xml { message: err.msg().to_string(), position: err.position() }
+    }
+}
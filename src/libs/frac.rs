@@ -1,44 +1,155 @@
 use std::cmp::Ordering;
-use fraction::{GenericFraction, Sign};
-use fraction::dynaint::DynaInt::S;
-use std::ops::{Add, Sub, Mul};
+use std::ops::{Add, Sub, Mul, Div, Rem, Neg, AddAssign, SubAssign, MulAssign, DivAssign, RemAssign};
+use std::iter::Sum;
+use std::convert::TryFrom;
+use std::str::FromStr;
 use std::fmt;
-use std::hash::{Hash, Hasher};
+use std::hash::Hash;
+use crate::libs::error::{MashError, MashResult, Location};
 
-#[derive(Debug)]
+/// Exact signed rational number, the type of offsets and durations.
+/// Kept in lowest terms with a positive denominator, so the derived Eq and Hash
+/// compare values. A zero denominator stands for an infinity, with a numerator of
+/// 1 or -1. Arithmetic is done in i128 and reduced before going back to i64; a
+/// result that still does not fit panics rather than wrapping.
+#[derive(Debug, PartialEq, Eq, Hash)]
 pub struct Frac {
-    _frac: GenericFraction<u32>
+    num: i64,
+    den: i64
 }
 
-/// Wrapper around GenericFraction to provide complete ordering
 impl Frac {
-    pub fn new(num: u32, den: u32) -> Self {
-        Frac {
-            _frac: GenericFraction::new(num, den)
+    /// num/den in lowest terms. den may be 0 for an infinity, but 0/0 panics
+    pub fn new(num: i64, den: i64) -> Self {
+        Self::from_i128(num as i128, den as i128)
+    }
+
+    /// Reduce num/den and bring it back to i64
+    fn from_i128(num: i128, den: i128) -> Self {
+        Self::checked_from_i128(num, den)
+            .unwrap_or_else(|| panic!("Frac {}/{} does not fit in i64", num, den))
+    }
+
+    /// from_i128, or None if the reduced fraction does not fit in i64
+    fn checked_from_i128(num: i128, den: i128) -> Option<Self> {
+        assert!(num != 0 || den != 0, "Frac 0/0 is undefined");
+        if den == 0 {
+            return Some(Frac { num: num.signum() as i64, den: 0 });
         }
+        let divisor = gcd_u128(num.unsigned_abs(), den.unsigned_abs()) as i128;
+        let sign = den.signum();
+        Some(Frac {
+            num: i64::try_from(sign * num / divisor).ok()?,
+            den: i64::try_from(sign * den / divisor).ok()?
+        })
     }
 
-    pub fn numer(&self) -> Option<&u32> {self._frac.numer()}
+    pub fn numer(&self) -> i64 {self.num}
 
-    pub fn denom(&self) -> Option<&u32> {self._frac.denom()}
+    /// 0 for infinities
+    pub fn denom(&self) -> i64 {self.den}
 
-    pub fn sign(&self) -> Option<Sign> {self._frac.sign()}
+    /// -1, 0 or 1
+    pub fn signum(&self) -> i64 {self.num.signum()}
 
     pub fn infinity() -> Self {
-        Self { _frac: GenericFraction::infinity() }
+        Frac { num: 1, den: 0 }
     }
 
     pub fn neg_infinity() -> Self {
-        Self { _frac: GenericFraction::neg_infinity() }
+        Frac { num: -1, den: 0 }
     }
 
+    pub fn is_infinite(&self) -> bool {
+        self.den == 0
+    }
+
+    pub fn is_finite(&self) -> bool {
+        self.den != 0
+    }
+
+    /// Parse a plain decimal such as "-1.25" or "7" exactly, the xs:decimal of
+    /// <divisions> and <duration>
+    pub fn from_decimal(text: &str) -> Option<Self> {
+        let (negative, digits) = match text.strip_prefix('-') {
+            Some(digits) => (true, digits),
+            None => (false, text.strip_prefix('+').unwrap_or(text))
+        };
+        let (whole, fraction) = match digits.find('.') {
+            Some(point) => (&digits[..point], &digits[point + 1..]),
+            None => (digits, "")
+        };
+        if whole.is_empty() && fraction.is_empty()
+            || !whole.chars().chain(fraction.chars()).all(|c| c.is_ascii_digit())
+        {
+            return None;
+        }
+        let mut num: i128 = 0;
+        let mut den: i128 = 1;
+        for digit in whole.chars().chain(fraction.chars()) {
+            num = num.checked_mul(10)?.checked_add(digit.to_digit(10)? as i128)?;
+        }
+        for _ in fraction.chars() {
+            den = den.checked_mul(10)?;
+        }
+        Self::checked_from_i128(if negative { -num } else { num }, den)
+    }
+
+    /// self written as a plain decimal such as "-1.25" or "7". None when it has no
+    /// exact one, e.g. for 1/3 or an infinity, or needs more digits than i128 holds
+    pub fn to_decimal(&self) -> Option<String> {
+        if self.den == 0 {
+            return None;
+        }
+        let (mut twos, mut fives, mut rest) = (0u32, 0u32, self.den);
+        while rest % 2 == 0 { rest /= 2; twos += 1; }
+        while rest % 5 == 0 { rest /= 5; fives += 1; }
+        if rest != 1 {
+            return None;
+        }
+        // num/den as scaled/10^places
+        let places = twos.max(fives);
+        let scaled = (self.num as i128)
+            .checked_mul(2i128.checked_pow(places - twos)?)?
+            .checked_mul(5i128.checked_pow(places - fives)?)?;
+        if places == 0 {
+            return Some(scaled.to_string());
+        }
+        let digits = format!("{:0width$}", scaled.unsigned_abs(), width = places as usize + 1);
+        let (whole, fraction) = digits.split_at(digits.len() - places as usize);
+        Some(format!("{}{}.{}", if scaled < 0 { "-" } else { "" }, whole, fraction))
+    }
+
+    /// Exact value of a float, as written by its shortest decimal form
+    fn from_float(f: f64, text: &str) -> Self {
+        if f.is_infinite() {
+            return if f > 0.0 { Self::infinity() } else { Self::neg_infinity() };
+        }
+        Self::from_decimal(text)
+            .unwrap_or_else(|| panic!("{} cannot be written as a Frac", text))
+    }
+}
+
+fn gcd_u128(mut a: u128, mut b: u128) -> u128 {
+    while b != 0 {
+        let rest = a % b;
+        a = b;
+        b = rest;
+    }
+    a
 }
 
 // Order Traits
 impl Ord for Frac {
     fn cmp(&self, other: &Self) -> Ordering {
-        (self._frac.numer().unwrap() * other._frac.denom().unwrap())
-            .cmp(&(self._frac.denom().unwrap() * other._frac.numer().unwrap()))
+        if self.is_finite() && other.is_finite() {
+            // denominators are positive, and i64 products cannot overflow i128
+            (self.num as i128 * other.den as i128).cmp(&(other.num as i128 * self.den as i128))
+        } else {
+            // -inf < every finite value < inf
+            let rank = |frac: &Frac| if frac.is_infinite() { frac.num } else { 0 };
+            rank(self).cmp(&rank(other))
+        }
     }
 }
 
@@ -48,82 +159,305 @@ impl PartialOrd for Frac {
     }
 }
 
-impl PartialEq for Frac {
-    fn eq(&self, other: &Self) -> bool {
-        (self._frac.numer().unwrap() * other._frac.denom().unwrap())
-            .eq(&(self._frac.denom().unwrap() * other._frac.numer().unwrap()))
+// Arithmetic trait
+impl Frac {
+    fn add_ref(&self, other: &Frac) -> Frac {
+        match (self.is_infinite(), other.is_infinite()) {
+            (false, false) => Self::from_i128(
+                self.num as i128 * other.den as i128 + other.num as i128 * self.den as i128,
+                self.den as i128 * other.den as i128
+            ),
+            (true, true) if self.num != other.num => panic!("Frac inf - inf is undefined"),
+            (true, _) => self.clone(),
+            (false, true) => other.clone()
+        }
+    }
+
+    fn sub_ref(&self, other: &Frac) -> Frac {
+        self.add_ref(&-other)
+    }
+
+    fn mul_ref(&self, other: &Frac) -> Frac {
+        if self.is_finite() && other.is_finite() {
+            Self::from_i128(self.num as i128 * other.num as i128, self.den as i128 * other.den as i128)
+        } else {
+            assert!(self.num != 0 && other.num != 0, "Frac 0 * inf is undefined");
+            Frac { num: self.signum() * other.signum(), den: 0 }
+        }
+    }
+
+    /// Dividing by 0 gives an infinity with the sign of self
+    fn div_ref(&self, other: &Frac) -> Frac {
+        if other.num == 0 {
+            assert!(self.num != 0, "Frac 0/0 is undefined");
+            return Frac { num: self.signum(), den: 0 };
+        }
+        self.mul_ref(&other.recip())
+    }
+
+    /// Remainder of the division truncated towards zero, as for integers
+    fn rem_ref(&self, other: &Frac) -> Frac {
+        assert!(self.is_finite() && other.is_finite() && other.num != 0,
+                "Frac {} % {} is undefined", self, other);
+        // over the common denominator self.den * other.den
+        let dividend = self.num as i128 * other.den as i128;
+        let divisor = other.num as i128 * self.den as i128;
+        Self::from_i128(dividend % divisor, self.den as i128 * other.den as i128)
+    }
+
+    /// 1/self. The reciprocal of 0 is infinity and of an infinity 0
+    pub fn recip(&self) -> Frac {
+        if self.is_infinite() {
+            return Frac { num: 0, den: 1 };
+        }
+        Self::new(self.den, self.num)
+    }
+
+    /// Value as a float, infinities included
+    pub fn to_f64(&self) -> f64 {
+        self.num as f64 / self.den as f64
+    }
+
+    /// Largest integer not above self. Infinities are returned as they are
+    pub fn floor(&self) -> Frac {
+        if self.is_infinite() {
+            return self.clone();
+        }
+        Frac { num: self.num.div_euclid(self.den), den: 1 }
+    }
+
+    /// Smallest integer not below self. Infinities are returned as they are
+    pub fn ceil(&self) -> Frac {
+        -(-self).floor()
+    }
+
+    /// Largest multiple of grid not above self, e.g. the start of the beat self
+    /// falls in with a grid of one beat
+    pub fn floor_to(&self, grid: &Frac) -> Frac {
+        assert!(grid.is_finite() && grid.num > 0, "grid must be positive, got {}", grid);
+        (self / grid).floor() * grid
+    }
+
+    /// Smallest multiple of grid not below self
+    pub fn ceil_to(&self, grid: &Frac) -> Frac {
+        assert!(grid.is_finite() && grid.num > 0, "grid must be positive, got {}", grid);
+        (self / grid).ceil() * grid
+    }
+
+    /// Largest fraction both self and other are whole multiples of, e.g. the
+    /// longest duration that can write both. Both must be finite
+    pub fn gcd(&self, other: &Frac) -> Frac {
+        assert!(self.is_finite() && other.is_finite(), "gcd of an infinite Frac");
+        Self::new(gcd(self.num, other.num), lcm(self.den, other.den))
+    }
+
+    /// Smallest fraction that is a whole multiple of both self and other. Both
+    /// must be finite
+    pub fn lcm(&self, other: &Frac) -> Frac {
+        assert!(self.is_finite() && other.is_finite(), "lcm of an infinite Frac");
+        if self.num == 0 || other.num == 0 {
+            return Frac::from(0u32);
+        }
+        Self::new(lcm(self.num, other.num), gcd(self.den, other.den))
+    }
+}
+
+/// Greatest common divisor, never negative. gcd(0, 0) is 0
+pub fn gcd(a: i64, b: i64) -> i64 {
+    i64::try_from(gcd_u128(a.unsigned_abs() as u128, b.unsigned_abs() as u128))
+        .expect("gcd of i64::MIN and 0 does not fit in i64")
+}
+
+/// Least common multiple, never negative, e.g. the divisions value that can write
+/// durations needing 3 and 4 divisions per quarter. Panics if it overflows i64
+pub fn lcm(a: i64, b: i64) -> i64 {
+    if a == 0 || b == 0 {
+        return 0;
     }
+    (a / gcd(a, b)).checked_mul(b)
+        .map(|lcm| lcm.abs())
+        .expect("lcm does not fit in i64")
 }
 
-impl Eq for Frac {}
+impl Neg for Frac {
+    type Output = Frac;
 
-// Arithmetic trait
-impl Add for Frac {
-    type Output = Self;
+    fn neg(self) -> Frac {
+        -&self
+    }
+}
+
+impl Neg for &Frac {
+    type Output = Frac;
+
+    fn neg(self) -> Frac {
+        Frac { num: -self.num, den: self.den }
+    }
+}
+
+/// Implements an operator for every mix of Frac and &Frac, and its assigning form,
+/// all through the by-reference method $method
+macro_rules! frac_binary_op {
+    ($Op:ident, $op:ident, $OpAssign:ident, $op_assign:ident, $method:ident) => {
+        impl<'a, 'b> $Op<&'b Frac> for &'a Frac {
+            type Output = Frac;
+
+            fn $op(self, other: &'b Frac) -> Frac {
+                self.$method(other)
+            }
+        }
+
+        impl<'b> $Op<&'b Frac> for Frac {
+            type Output = Frac;
+
+            fn $op(self, other: &'b Frac) -> Frac {
+                self.$method(other)
+            }
+        }
+
+        impl<'a> $Op<Frac> for &'a Frac {
+            type Output = Frac;
 
-    fn add(self, other: Self) -> Self {
-        Self { _frac: self._frac + other._frac }
+            fn $op(self, other: Frac) -> Frac {
+                self.$method(&other)
+            }
+        }
+
+        impl $Op for Frac {
+            type Output = Frac;
+
+            fn $op(self, other: Frac) -> Frac {
+                self.$method(&other)
+            }
+        }
+
+        impl<'b> $OpAssign<&'b Frac> for Frac {
+            fn $op_assign(&mut self, other: &'b Frac) {
+                *self = self.$method(other);
+            }
+        }
+
+        impl $OpAssign for Frac {
+            fn $op_assign(&mut self, other: Frac) {
+                *self = self.$method(&other);
+            }
+        }
+    };
+}
+
+frac_binary_op!(Add, add, AddAssign, add_assign, add_ref);
+frac_binary_op!(Sub, sub, SubAssign, sub_assign, sub_ref);
+frac_binary_op!(Mul, mul, MulAssign, mul_assign, mul_ref);
+frac_binary_op!(Div, div, DivAssign, div_assign, div_ref);
+frac_binary_op!(Rem, rem, RemAssign, rem_assign, rem_ref);
+
+impl Sum for Frac {
+    fn sum<I: Iterator<Item = Frac>>(iter: I) -> Frac {
+        iter.fold(Frac::from(0u32), |total, frac| total + frac)
+    }
+}
+
+impl<'a> Sum<&'a Frac> for Frac {
+    fn sum<I: Iterator<Item = &'a Frac>>(iter: I) -> Frac {
+        iter.fold(Frac::from(0u32), |total, frac| total + frac)
+    }
+}
+
+// Formatting and parsing
+/// "3/4", "7" for whole numbers, "inf" and "-inf"
+impl fmt::Display for Frac {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.den {
+            0 if self.num > 0 => write!(f, "inf"),
+            0 => write!(f, "-inf"),
+            1 => write!(f, "{}", self.num),
+            _ => write!(f, "{}/{}", self.num, self.den)
+        }
     }
 }
 
-impl Sub for Frac {
-    type Output = Self;
+/// Reads what Display writes, and decimals such as "1.5" exactly
+impl FromStr for Frac {
+    type Err = MashError;
 
-    fn sub(self, other: Self) -> Self {
-        Self { _frac: self._frac - other._frac }
+    fn from_str(text: &str) -> MashResult<Self> {
+        let text = text.trim();
+        let invalid = || MashError::InvalidValue { value: text.to_string(), location: Location::default() };
+        match text {
+            "inf" | "+inf" => return Ok(Self::infinity()),
+            "-inf" => return Ok(Self::neg_infinity()),
+            _ => {}
+        }
+        match text.find('/') {
+            Some(slash) => {
+                let num: i64 = text[..slash].trim().parse().map_err(|_| invalid())?;
+                let den: i64 = text[slash + 1..].trim().parse().map_err(|_| invalid())?;
+                if den == 0 {
+                    return Err(invalid());
+                }
+                Ok(Self::new(num, den))
+            }
+            None => Self::from_decimal(text).ok_or_else(invalid)
+        }
     }
 }
 
 // From<T> traits
 impl From<u16> for Frac {
     fn from(num: u16) -> Self {
-        Self { _frac: GenericFraction::from(num) }
+        Frac { num: num as i64, den: 1 }
     }
 }
 
 impl From<u32> for Frac {
     fn from(num: u32) -> Self {
-        Self { _frac: GenericFraction::from(num) }
+        Frac { num: num as i64, den: 1 }
+    }
+}
+
+impl From<i32> for Frac {
+    fn from(num: i32) -> Self {
+        Frac { num: num as i64, den: 1 }
+    }
+}
+
+impl From<i64> for Frac {
+    fn from(num: i64) -> Self {
+        Frac { num, den: 1 }
     }
 }
 
 impl<N, D> From<(N, D)> for Frac
-where N: fmt::Display,
-      D: fmt::Display
+where N: Into<i64>,
+      D: Into<i64>
 {
     fn from(pair: (N, D)) -> Self {
-        Self { _frac: GenericFraction::from(pair) }
+        Self::new(pair.0.into(), pair.1.into())
     }
 }
 
 impl From<f32> for Frac {
     fn from(f: f32) -> Self {
-        Self { _frac: GenericFraction::from(f) }
+        Self::from_float(f as f64, &f.to_string())
     }
 }
 
 impl From<f64> for Frac {
     fn from(f: f64) -> Self {
-        Self { _frac: GenericFraction::from(f) }
+        Self::from_float(f, &f.to_string())
     }
 }
 
 // Cloning
 impl Clone for Frac {
     fn clone(&self) -> Self {
-        Self { _frac: self._frac.clone() }
-    }
-}
-
-// Hash
-impl Hash for Frac {
-    fn hash<H: Hasher>(&self, state: &mut H) {
-        self._frac.hash(state);
+        Frac { num: self.num, den: self.den }
     }
 }
 
+#[cfg(test)]
 mod tests {
-    use super::Frac;
+    use super::{Frac, gcd, lcm};
     use std::collections::BTreeMap;
 
     #[test]
@@ -136,4 +470,127 @@ mod tests {
         println!("{:#?}", a + Frac::from(1u32) == Frac::from((17, 2)));
         let m: BTreeMap<Frac, String> = BTreeMap::new();
     }
+
+    #[test]
+    fn test_signed () {
+        // a backup past the start of a measure
+        let offset = Frac::new(1, 2) - Frac::from(2u32);
+        assert_eq!(offset, Frac::new(-3, 2));
+        assert_eq!(Frac::new(3, -6), Frac::new(-1, 2));
+        assert_eq!((offset.numer(), offset.denom()), (-3, 2));
+        assert!(offset < Frac::from(0u32));
+    }
+
+    #[test]
+    fn test_large_divisions () {
+        // cross-multiplying these overflowed u32
+        let divisions = 3_000_000_019i64;
+        let a = Frac::new(divisions - 1, divisions);
+        let b = Frac::new(divisions - 2, divisions - 1);
+        assert!(b < a);
+        assert_eq!(a.clone() - a.clone() + b.clone(), b);
+        // the sum only fits once reduced
+        let tiny = Frac::new(1, i64::MAX - 1);
+        assert_eq!(tiny.clone() + tiny, Frac::new(1, (i64::MAX - 1) / 2));
+    }
+
+    #[test]
+    fn test_infinity () {
+        let values = vec![Frac::infinity(), Frac::from(7u32), Frac::neg_infinity(), Frac::new(-5, 2)];
+        let mut sorted = values.clone();
+        sorted.sort();
+        assert_eq!(sorted, vec![Frac::neg_infinity(), Frac::new(-5, 2), Frac::from(7u32), Frac::infinity()]);
+        assert_eq!(Frac::new(-4, 0), Frac::neg_infinity());
+        assert_eq!(Frac::infinity() + Frac::from(1u32), Frac::infinity());
+        assert!(Frac::infinity().is_infinite() && Frac::from(0u32).is_finite());
+    }
+
+    #[test]
+    fn test_ops () {
+        let quarter = Frac::new(1, 4);
+        // a quarter note under a 3:2 tuplet
+        let tuplet = &quarter * Frac::new(2, 3);
+        assert_eq!(tuplet, Frac::new(1, 6));
+        assert_eq!(&tuplet / &quarter, Frac::new(2, 3));
+        assert_eq!(-&tuplet, Frac::new(-1, 6));
+        assert_eq!(Frac::new(7, 4) % Frac::new(1, 2), Frac::new(1, 4));
+        assert_eq!(Frac::new(-7, 4) % Frac::new(1, 2), Frac::new(-1, 4));
+        assert_eq!(Frac::from(1u32) / Frac::from(0u32), Frac::infinity());
+        assert_eq!(Frac::infinity().recip(), Frac::from(0u32));
+
+        let mut cursor = Frac::from(0u32);
+        cursor += &quarter;
+        cursor += Frac::new(1, 2);
+        cursor -= &tuplet;
+        cursor *= Frac::from(6u32);
+        cursor /= &Frac::from(2u32);
+        assert_eq!(cursor, Frac::new(7, 4));
+        cursor %= Frac::from(1u32);
+        assert_eq!(cursor, Frac::new(3, 4));
+
+        let durations = vec![Frac::new(1, 2), Frac::new(1, 3), Frac::new(1, 6)];
+        assert_eq!(durations.iter().sum::<Frac>(), Frac::from(1u32));
+        assert_eq!(durations.into_iter().sum::<Frac>(), Frac::from(1u32));
+    }
+
+    #[test]
+    fn test_format_parse () {
+        assert_eq!(Frac::new(3, 4).to_string(), "3/4");
+        assert_eq!(Frac::new(-14, 2).to_string(), "-7");
+        assert_eq!(Frac::neg_infinity().to_string(), "-inf");
+        assert_eq!("3/4".parse::<Frac>().unwrap(), Frac::new(3, 4));
+        assert_eq!(" -6 / 8 ".parse::<Frac>().unwrap(), Frac::new(-3, 4));
+        assert_eq!("1.5".parse::<Frac>().unwrap(), Frac::new(3, 2));
+        assert_eq!("7".parse::<Frac>().unwrap(), Frac::from(7u32));
+        assert_eq!("inf".parse::<Frac>().unwrap(), Frac::infinity());
+        for frac in [Frac::new(-5, 3), Frac::from(0u32), Frac::infinity()].iter() {
+            assert_eq!(&frac.to_string().parse::<Frac>().unwrap(), frac);
+        }
+        for text in ["", "3/", "3/0", "1.2.3", "a/4", "--1", "."].iter() {
+            assert!(text.parse::<Frac>().is_err(), "{:?} parsed", text);
+        }
+        assert_eq!(Frac::new(-5, 4).to_decimal().as_deref(), Some("-1.25"));
+        assert_eq!(Frac::new(1, 20).to_decimal().as_deref(), Some("0.05"));
+        assert_eq!(Frac::from(480u32).to_decimal().as_deref(), Some("480"));
+        assert_eq!(Frac::new(1, 3).to_decimal(), None);
+        assert_eq!(Frac::infinity().to_decimal(), None);
+        assert_eq!(Frac::from_decimal("2.5"), Some(Frac::new(5, 2)));
+        assert_eq!(Frac::from_decimal("3/4"), None);
+        assert_eq!(Frac::new(3, 8).to_f64(), 0.375);
+        assert_eq!(Frac::neg_infinity().to_f64(), f64::NEG_INFINITY);
+    }
+
+    #[test]
+    fn test_grid () {
+        let eighth = Frac::new(1, 2);
+        assert_eq!(Frac::new(7, 3).floor(), Frac::from(2u32));
+        assert_eq!(Frac::new(-7, 3).floor(), Frac::from(-3));
+        assert_eq!(Frac::new(7, 3).ceil(), Frac::from(3u32));
+        assert_eq!(Frac::new(-7, 3).ceil(), Frac::from(-2));
+        assert_eq!(Frac::new(7, 3).floor_to(&eighth), Frac::from(2u32));
+        assert_eq!(Frac::new(7, 3).ceil_to(&eighth), Frac::new(5, 2));
+        assert_eq!(Frac::from(3u32).floor_to(&eighth), Frac::from(3u32));
+    }
+
+    #[test]
+    fn test_gcd_lcm () {
+        assert_eq!(gcd(12, -18), 6);
+        assert_eq!(lcm(4, 6), 12);
+        assert_eq!(lcm(0, 6), 0);
+        // divisions able to write an eighth triplet and a sixteenth
+        let triplet = Frac::new(1, 3);
+        let sixteenth = Frac::new(1, 4);
+        let unit = triplet.gcd(&sixteenth);
+        assert_eq!(unit, Frac::new(1, 12));
+        assert_eq!(unit.recip(), Frac::from(12u32));
+        assert_eq!(triplet.lcm(&sixteenth), Frac::from(1u32));
+        assert_eq!(Frac::new(3, 4).lcm(&Frac::new(1, 2)), Frac::new(3, 2));
+    }
+
+    #[test]
+    fn test_from_float () {
+        assert_eq!(Frac::from(0.1f64), Frac::new(1, 10));
+        assert_eq!(Frac::from(-2.5f32), Frac::new(-5, 2));
+        assert_eq!(Frac::from(f64::NEG_INFINITY), Frac::neg_infinity());
+    }
 }
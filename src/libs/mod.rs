@@ -1 +1,2 @@
-pub mod frac;
\ No newline at end of file
+pub mod frac;
+pub mod error;
\ No newline at end of file
@@ -5,21 +5,490 @@ use std::io::Write;
 use xml::EventWriter;
 use xml::name::OwnedName;
 use xml::namespace::Namespace;
-use std::collections::BTreeMap;
+use std::collections::{BTreeMap, HashSet};
+use crate::libs::error::{MashError, MashResult, Location};
+use crate::libs::frac::Frac;
+use crate::msc::gnote::note_attr::{Step, Divisions, Duration};
+use xml::attribute::OwnedAttribute;
 
-pub type TimeSignature = (u8, u8);
+#[derive(Debug, Clone, PartialEq)]
+pub enum TimeSymbol {
+    Common, Cut, SingleNumber, Note, DottedNote, Normal
+}
+impl TimeSymbol {
+    /// The error has no location, callers add the one of the symbol attribute
+    fn try_from_str(symbol: &str) -> MashResult<Self> {
+        match symbol {
+            "common" => Ok(Self::Common),
+            "cut" => Ok(Self::Cut),
+            "single-number" => Ok(Self::SingleNumber),
+            "note" => Ok(Self::Note),
+            "dotted-note" => Ok(Self::DottedNote),
+            "normal" => Ok(Self::Normal),
+            _ => Err(MashError::InvalidValue { value: symbol.to_string(), location: Location::default() })
+        }
+    }
+
+    fn to_str(&self) -> &str {
+        match self {
+            Self::Common => "common",
+            Self::Cut => "cut",
+            Self::SingleNumber => "single-number",
+            Self::Note => "note",
+            Self::DottedNote => "dotted-note",
+            Self::Normal => "normal"
+        }
+    }
+}
+
+/// How the beats of a meter are grouped
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MeterKind {
+    /// Beats split in two, e.g. 2/4 or 3/4
+    Simple,
+    /// Dotted beats split in three, e.g. 6/8 or 12/8
+    Compound,
+    /// Beats of unequal length, e.g. 3+2/8 or 2/4 followed by 3/8 in one <time>
+    Additive
+}
+
+/// One <beats>/<beat-type> pair
+#[derive(Debug, Clone, PartialEq)]
+pub struct TimeBeats {
+    pub beats: Vec< u8>, // the summands of an additive numerator such as 3+2, a single one otherwise
+    pub beat_type: u8
+}
+
+impl TimeBeats {
+    /// Length in quarter notes
+    pub fn duration(&self) -> Frac {
+        let beats: i64 = self.beats.iter().map(|beats| *beats as i64).sum();
+        Frac::new(4 * beats, self.beat_type as i64)
+    }
+
+    fn beats_to_string(&self) -> String {
+        self.beats.iter().map(|beats| beats.to_string()).collect::<Vec<_>>().join("+")
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct TimeSignature {
+    // attributes of <time> other than symbol, e.g. number or print-object, tags
+    // we dont model such as <interchangeable>, then its comments, see
+    // XmlTag::anchored_comments
+    _xml_attrs: Vec< OwnedAttribute>,
+    _xml_tags: Vec< XmlTag>,
+
+    pub beats: Vec< TimeBeats>, // more than one for composite meters, e.g. 2/4+3/8
+    pub symbol: Option<TimeSymbol>,
+    pub senza_misura: Option<String> // <senza-misura> with its text, which may be empty
+}
+
+const MODELED_TIME_TAGS: [&str; 3] = ["beats", "beat-type", "senza-misura"];
+
+impl TimeSignature {
+    pub fn new(beats: u8, beat_type: u8) -> Self {
+        TimeSignature {
+            _xml_attrs: Vec::new(),
+            _xml_tags: Vec::new(),
+            beats: vec![TimeBeats { beats: vec![beats], beat_type }],
+            symbol: None,
+            senza_misura: None
+        }
+    }
+
+    pub fn from_xml_tag(xml_tag: &XmlTag) -> Self {
+        Self::try_from_xml_tag(xml_tag).unwrap()
+    }
+
+    pub fn try_from_xml_tag(xml_tag: &XmlTag) -> MashResult<Self> {
+        let mut time = TimeSignature {
+            _xml_attrs: xml_tag.attributes.iter()
+                .filter(|attr| attr.name.local_name != "symbol")
+                .cloned()
+                .collect(),
+            _xml_tags: Vec::new(),
+            beats: Vec::new(),
+            symbol: match xml_tag.get_attribute_value("symbol") {
+                Some(symbol) => Some(TimeSymbol::try_from_str(&symbol).map_err(|_| MashError::InvalidValue {
+                    value: symbol, location: Location::of_path(xml_tag, "@symbol")
+                })?),
+                None => None
+            },
+            senza_misura: None
+        };
+        // a <beat-type> for every <beats>, in turn
+        let mut beat_type_missing = false;
+        for child_tag in xml_tag.child_tags.iter() {
+            let in_time = |err: MashError| err.within(xml_tag);
+            match child_tag.name.local_name.as_str() {
+                "beats" => {
+                    if beat_type_missing {
+                        return Err(MashError::MissingTag { location: Location::of_path(xml_tag, "beat-type") });
+                    }
+                    let text = child_tag.require_text().map_err(in_time)?;
+                    // a bar of no beats has no length
+                    let beats = text.split('+')
+                        .map(|beats| beats.trim().parse())
+                        .collect::<Result<Vec< u8>, _>>()
+                        .ok()
+                        .filter(|beats| beats.iter().any(|beats| *beats > 0))
+                        .ok_or_else(|| MashError::InvalidValue {
+                            value: text.to_string(), location: Location::of(child_tag)
+                        }.within(xml_tag))?;
+                    time.beats.push(TimeBeats { beats, beat_type: 0 });
+                    beat_type_missing = true;
+                }
+                "beat-type" => {
+                    let text = child_tag.require_text().map_err(in_time)?;
+                    let last = time.beats.last_mut()
+                        .filter(|_| beat_type_missing)
+                        .ok_or_else(|| MashError::MissingTag { location: Location::of_path(xml_tag, "beats") })?;
+                    last.beat_type = text.trim().parse().ok()
+                        .filter(|beat_type| *beat_type > 0)
+                        .ok_or_else(|| MashError::InvalidValue {
+                            value: text.to_string(), location: Location::of(child_tag)
+                        }.within(xml_tag))?;
+                    beat_type_missing = false;
+                }
+                "senza-misura" => time.senza_misura = Some(child_tag.text.clone().unwrap_or_default()),
+                _ => if child_tag.is_element() && !MODELED_TIME_TAGS.contains(&child_tag.name.local_name.as_str()) {
+                    time._xml_tags.push(child_tag.clone());
+                }
+            }
+        }
+        time._xml_tags.extend(xml_tag.anchored_comments());
+        if beat_type_missing {
+            return Err(MashError::MissingTag { location: Location::of_path(xml_tag, "beat-type") });
+        }
+        if time.beats.is_empty() && time.senza_misura.is_none() {
+            return Err(MashError::MissingTag { location: Location::of_path(xml_tag, "beats") });
+        }
+        Ok(time)
+    }
+
+    /// Length of one measure in quarter notes, e.g. 3/2 for 3/8 and 5/2 for 3+2/8.
+    /// None for senza-misura, which has no barlines to measure, and for a beat type of 0
+    pub fn bar_duration(&self) -> Option<Frac> {
+        if self.senza_misura.is_some() || self.beats.is_empty() || self.beats.iter().any(|pair| pair.beat_type == 0) {
+            return None;
+        }
+        Some(self.beats.iter().map(|beats| beats.duration()).sum())
+    }
+
+    pub fn kind(&self) -> MeterKind {
+        match self.beats.as_slice() {
+            [TimeBeats { beats, .. }] if beats.len() == 1 =>
+                if beats[0] > 3 && beats[0] % 3 == 0 { MeterKind::Compound } else { MeterKind::Simple },
+            _ => MeterKind::Additive
+        }
+    }
+
+    /// Lengths in quarter notes of the beats notes are grouped by: a dotted
+    /// quarter twice for 6/8, a quarter then a dotted quarter for 2+3/8
+    pub fn beat_groups(&self) -> Vec< Frac> {
+        let mut groups = Vec::new();
+        for pair in self.beats.iter() {
+            let unit = Frac::new(4, pair.beat_type as i64);
+            match self.kind() {
+                MeterKind::Simple => groups.extend((0..pair.beats[0]).map(|_| unit.clone())),
+                MeterKind::Compound => groups.extend((0..pair.beats[0] / 3).map(|_| &unit * Frac::from(3u32))),
+                MeterKind::Additive => groups.extend(pair.beats.iter().map(|beats| &unit * Frac::from(*beats as u32)))
+            }
+        }
+        groups
+    }
+
+    pub fn to_xml_tag(&self) -> XmlTag {
+        let mut time = XmlTag::new("time");
+        if let Some(symbol) = self.symbol.as_ref() {
+            time.add_attribute("symbol", symbol.to_str());
+        }
+        time.add_attributes(&self._xml_attrs);
+        // add_tag would return the pair already written, build each tag instead
+        for pair in self.beats.iter() {
+            let mut beats = XmlTag::new("beats");
+            beats.add_text(pair.beats_to_string());
+            let mut beat_type = XmlTag::new("beat-type");
+            beat_type.add_text(pair.beat_type.to_string());
+            time.direct_add_tag(beats).direct_add_tag(beat_type);
+        }
+        for tag in self._xml_tags.iter().filter(|tag| tag.is_element()) {
+            time.direct_add_tag(tag.clone());
+        }
+        if let Some(senza_misura) = self.senza_misura.as_ref() {
+            let senza_misura_tag = time.add_tag("senza-misura");
+            if !senza_misura.is_empty() {
+                senza_misura_tag.add_text(senza_misura);
+            }
+        }
+        time.restore_comments(self._xml_tags.iter().filter(|tag| !tag.is_element()));
+        time
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum KeyMode {
+    Major, Minor,
+    Ionian, Dorian, Phrygian, Lydian, Mixolydian, Aeolian, Locrian,
+    /// <mode>none</mode>, a key without a tonal center
+    None
+}
+impl KeyMode {
+    /// The error has no location, callers add the one of <mode>
+    fn try_from_str(mode: &str) -> MashResult<Self> {
+        match mode {
+            "major" => Ok(Self::Major),
+            "minor" => Ok(Self::Minor),
+            "ionian" => Ok(Self::Ionian),
+            "dorian" => Ok(Self::Dorian),
+            "phrygian" => Ok(Self::Phrygian),
+            "lydian" => Ok(Self::Lydian),
+            "mixolydian" => Ok(Self::Mixolydian),
+            "aeolian" => Ok(Self::Aeolian),
+            "locrian" => Ok(Self::Locrian),
+            "none" => Ok(Self::None),
+            _ => Err(MashError::InvalidValue { value: mode.to_string(), location: Location::default() })
+        }
+    }
+
+    fn to_str(&self) -> &str {
+        match self {
+            Self::Major => "major",
+            Self::Minor => "minor",
+            Self::Ionian => "ionian",
+            Self::Dorian => "dorian",
+            Self::Phrygian => "phrygian",
+            Self::Lydian => "lydian",
+            Self::Mixolydian => "mixolydian",
+            Self::Aeolian => "aeolian",
+            Self::Locrian => "locrian",
+            Self::None => "none"
+        }
+    }
+
+    /// Fifths from the major tonic of a key signature up to the tonic of this mode,
+    /// e.g. 3 for minor: A minor shares the signature of C major. None for Self::None
+    fn tonic_fifths(&self) -> Option<i8> {
+        match self {
+            Self::Lydian => Some(-1),
+            Self::Major | Self::Ionian => Some(0),
+            Self::Mixolydian => Some(1),
+            Self::Dorian => Some(2),
+            Self::Minor | Self::Aeolian => Some(3),
+            Self::Phrygian => Some(4),
+            Self::Locrian => Some(5),
+            Self::None => None
+        }
+    }
+}
+
+/// Steps in the order sharps are added to a key signature, flats go the other way
+const STEPS_BY_FIFTHS: [Step; 7] = [Step::F, Step::C, Step::G, Step::D, Step::A, Step::E, Step::B];
+
+/// One altered step of a non-traditional key
+#[derive(Debug, Clone, PartialEq)]
+pub struct KeyStep {
+    pub step: Step,
+    pub alter: i8, // semitones
+    pub accidental: Option<String> // <key-accidental>, when it differs from what alter implies
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum KeyAlterations {
+    /// A key on the circle of fifths: sharps when positive, flats when negative
+    Fifths(i8),
+    /// <key-step>/<key-alter> pairs, each step listed at most once
+    Steps(Vec< KeyStep>)
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct KeySignature {
+    // attributes of <key>, e.g. number or print-object, tags we dont model such
+    // as <key-octave>, then its comments
+    _xml_attrs: Vec< OwnedAttribute>,
+    _xml_tags: Vec< XmlTag>,
+
+    pub cancel: Option<i8>, // fifths of the previous key, whose accidentals get naturals
+    pub alterations: KeyAlterations,
+    pub mode: Option<KeyMode>
+}
+
+const MODELED_KEY_TAGS: [&str; 6] = ["cancel", "fifths", "mode", "key-step", "key-alter", "key-accidental"];
+
+impl KeySignature {
+    pub fn new(fifths: i8, mode: Option<KeyMode>) -> Self {
+        KeySignature {
+            _xml_attrs: Vec::new(),
+            _xml_tags: Vec::new(),
+            cancel: None,
+            alterations: KeyAlterations::Fifths(fifths),
+            mode
+        }
+    }
+
+    pub fn from_xml_tag(xml_tag: &XmlTag) -> Self {
+        Self::try_from_xml_tag(xml_tag).unwrap()
+    }
+
+    /// Parse a <key>, traditional with <fifths> or non-traditional with
+    /// <key-step>/<key-alter> pairs
+    pub fn try_from_xml_tag(xml_tag: &XmlTag) -> MashResult<Self> {
+        let alterations = match xml_tag.try_get_tag_content_as("fifths")? {
+            Some(fifths) => KeyAlterations::Fifths(fifths),
+            None => {
+                let mut steps: Vec< KeyStep> = Vec::new();
+                for child_tag in xml_tag.child_tags.iter() {
+                    let in_key = |err: MashError| err.within(xml_tag);
+                    match child_tag.name.local_name.as_str() {
+                        "key-step" => {
+                            let step = child_tag.require_text().map_err(in_key)?;
+                            steps.push(KeyStep {
+                                step: Step::try_from_str(step).map_err(|_| MashError::InvalidValue {
+                                    value: step.to_string(), location: Location::of(child_tag)
+                                }.within(xml_tag))?,
+                                alter: 0,
+                                accidental: None
+                            });
+                        }
+                        "key-alter" | "key-accidental" => {
+                            let text = child_tag.require_text().map_err(in_key)?;
+                            let last = steps.last_mut().ok_or_else(|| MashError::MissingTag {
+                                location: Location::of_path(xml_tag, "key-step")
+                            })?;
+                            if child_tag.name.local_name == "key-alter" {
+                                last.alter = text.parse().map_err(|_| MashError::InvalidValue {
+                                    value: text.to_string(), location: Location::of(child_tag)
+                                }.within(xml_tag))?;
+                            } else {
+                                last.accidental = Some(text.to_string());
+                            }
+                        }
+                        _ => {}
+                    }
+                }
+                if steps.is_empty() {
+                    return Err(MashError::MissingTag { location: Location::of_path(xml_tag, "fifths") });
+                }
+                KeyAlterations::Steps(steps)
+            }
+        };
+        let mode = match xml_tag.try_get_tag_content("mode")? {
+            Some(mode) => Some(KeyMode::try_from_str(&mode).map_err(|_| MashError::InvalidValue {
+                value: mode, location: Location::of_path(xml_tag, "mode")
+            })?),
+            None => None
+        };
+        let mut key = KeySignature {
+            _xml_attrs: xml_tag.attributes.clone(),
+            _xml_tags: Vec::new(),
+            cancel: xml_tag.try_get_tag_content_as("cancel")?,
+            alterations,
+            mode
+        };
+        for child_tag in xml_tag.child_tags.iter() {
+            if child_tag.is_element() && !MODELED_KEY_TAGS.contains(&child_tag.name.local_name.as_str()) {
+                key._xml_tags.push(child_tag.clone());
+            }
+        }
+        key._xml_tags.extend(xml_tag.anchored_comments());
+        Ok(key)
+    }
+
+    /// Alteration in semitones the key gives step, e.g. 1 for F in D major
+    pub fn alter_of(&self, step: &Step) -> i8 {
+        match &self.alterations {
+            KeyAlterations::Fifths(fifths) => {
+                // a key with more than 7 sharps or flats alters some steps twice.
+                // In i16, since -i8::MIN does not fit an i8; the result is at most 19
+                let count = |accidentals: i16, nth: i16| if accidentals > nth { (accidentals - 1 - nth) / 7 + 1 } else { 0 };
+                let nth = STEPS_BY_FIFTHS.iter().position(|by_fifths| by_fifths == step).unwrap() as i16;
+                let fifths = *fifths as i16;
+                let alter = if fifths >= 0 { count(fifths, nth) } else { -count(-fifths, 6 - nth) };
+                alter as i8
+            }
+            KeyAlterations::Steps(steps) => steps.iter()
+                .find(|key_step| &key_step.step == step)
+                .map_or(0, |key_step| key_step.alter)
+        }
+    }
+
+    /// Step and alteration of the tonic, e.g. (F, 1) for 3 sharps in minor.
+    /// A key without <mode> is taken as major. None for non-traditional keys and
+    /// <mode>none</mode>
+    pub fn tonic(&self) -> Option<(Step, i8)> {
+        let fifths = match self.alterations {
+            KeyAlterations::Fifths(fifths) => fifths,
+            KeyAlterations::Steps(_) => return None
+        };
+        // in i16, as fifths near the ends of i8 would overflow
+        let tonic = fifths as i16 + self.mode.as_ref().unwrap_or(&KeyMode::Major).tonic_fifths()? as i16;
+        // counted from F, the first step of STEPS_BY_FIFTHS
+        let from_f = tonic + 1;
+        Some((STEPS_BY_FIFTHS[from_f.rem_euclid(7) as usize].clone(), from_f.div_euclid(7) as i8))
+    }
+
+    pub fn to_xml_tag(&self) -> XmlTag {
+        let mut key = XmlTag::new("key");
+        key.add_attributes(&self._xml_attrs);
+        if let Some(cancel) = self.cancel {
+            key.add_tag("cancel").add_text(cancel.to_string());
+        }
+        match &self.alterations {
+            KeyAlterations::Fifths(fifths) => {
+                key.add_tag("fifths").add_text(fifths.to_string());
+            }
+            KeyAlterations::Steps(steps) => {
+                // add_tag would return the pair already written, build each tag instead
+                for key_step in steps.iter() {
+                    let mut text_tag = |name: &str, text: &str| {
+                        let mut tag = XmlTag::new(name);
+                        tag.add_text(text);
+                        key.direct_add_tag(tag);
+                    };
+                    text_tag("key-step", key_step.step.to_str());
+                    text_tag("key-alter", &key_step.alter.to_string());
+                    if let Some(accidental) = key_step.accidental.as_ref() {
+                        text_tag("key-accidental", accidental);
+                    }
+                }
+            }
+        }
+        if let Some(mode) = self.mode.as_ref() {
+            key.add_tag("mode").add_text(mode.to_str());
+        }
+        for tag in self._xml_tags.iter().filter(|tag| tag.is_element()) {
+            key.direct_add_tag(tag.clone());
+        }
+        key.order_by_schema();
+        key.restore_comments(self._xml_tags.iter().filter(|tag| !tag.is_element()));
+        key
+    }
+}
 
-#[derive(Debug)]
+#[derive(Debug, Clone, PartialEq)]
 pub enum ClefSign {
-    G, F, C
+    G, F, C,
+    Percussion,
+    Tab,
+    /// <sign>none</sign>, a staff drawn without a clef
+    None
 }
 impl ClefSign {
     fn from_str(sign: &str) -> Self {
+        Self::try_from_str(sign).unwrap()
+    }
+
+    /// The error has no location, callers add the one of <sign>
+    fn try_from_str(sign: &str) -> MashResult<Self> {
         match sign {
-            "G" => Self::G,
-            "F" => Self::F,
-            "C" => Self::C,
-            _ => unreachable!()
+            "G" => Ok(Self::G),
+            "F" => Ok(Self::F),
+            "C" => Ok(Self::C),
+            "percussion" => Ok(Self::Percussion),
+            "TAB" => Ok(Self::Tab),
+            "none" => Ok(Self::None),
+            _ => Err(MashError::InvalidValue { value: sign.to_string(), location: Location::default() })
         }
     }
 
@@ -28,99 +497,291 @@ impl ClefSign {
             Self::G => "G",
             Self::F => "F",
             Self::C => "C",
-            _ => unreachable!()
+            Self::Percussion => "percussion",
+            Self::Tab => "TAB",
+            Self::None => "none"
         }
     }
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone, PartialEq)]
 pub struct Clef {
-    sign: ClefSign,
-    line: u8
+    pub sign: ClefSign,
+    pub line: Option<u8>, // staff line of the sign counted from the bottom, often left out for percussion
+    pub octave_change: Option<i8> // <clef-octave-change>, e.g. -1 for a tenor's treble clef
+}
+
+impl Clef {
+    pub fn new(sign: ClefSign, line: u8) -> Self {
+        Clef { sign, line: Some(line), octave_change: None }
+    }
+
+    /// Parse a <clef>. Returns the staff it applies to along with it
+    pub fn try_from_xml_tag(xml_tag: &XmlTag) -> MashResult<(u8, Clef)> {
+        let sign: String = xml_tag.require_tag_content_as("sign")?;
+        let clef = Clef {
+            sign: ClefSign::try_from_str(&sign)
+                .map_err(|_| MashError::InvalidValue {
+                    value: sign, location: Location::of_path(xml_tag, "sign")
+                })?,
+            line: xml_tag.try_get_tag_content_as("line")?,
+            octave_change: xml_tag.try_get_tag_content_as("clef-octave-change")?
+        };
+        Ok((xml_tag.try_get_attribute_as("number")?.unwrap_or(1), clef))
+    }
+
+    /// Whether notes on the staff have pitches, false for percussion and TAB staves
+    pub fn is_pitched(&self) -> bool {
+        !matches!(self.sign, ClefSign::Percussion | ClefSign::Tab)
+    }
+
+    /// <clef>, numbered when staff is not given
+    pub fn to_xml_tag(&self, staff: Option<u8>) -> XmlTag {
+        let mut clef = XmlTag::new("clef");
+        if let Some(staff) = staff {
+            clef.add_attribute("number", staff.to_string());
+        }
+        clef.add_tag("sign").add_text(self.sign.to_str());
+        if let Some(line) = self.line {
+            clef.add_tag("line").add_text(line.to_string());
+        }
+        if let Some(octave_change) = self.octave_change {
+            clef.add_tag("clef-octave-change").add_text(octave_change.to_string());
+        }
+        clef
+    }
+}
+
+/// <staff-details> of one staff. Children other than <staff-type> and <staff-lines>,
+/// e.g. the <staff-tuning> of a TAB staff, are kept as they were read
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct StaffDetails {
+    _xml_tags: Vec< XmlTag>,
+
+    pub staff_type: Option<String>, // ossia, cue, editorial, regular or alternate
+    pub staff_lines: Option<u8>
+}
+
+const MODELED_STAFF_DETAILS_TAGS: [&str; 2] = ["staff-type", "staff-lines"];
+
+impl StaffDetails {
+    /// Parse a <staff-details>. Returns the staff it applies to along with it
+    pub fn try_from_xml_tag(xml_tag: &XmlTag) -> MashResult<(u8, StaffDetails)> {
+        let mut details = StaffDetails {
+            _xml_tags: Vec::new(),
+            staff_type: xml_tag.try_get_tag_content("staff-type")?,
+            staff_lines: xml_tag.try_get_tag_content_as("staff-lines")?
+        };
+        for child_tag in xml_tag.child_tags.iter() {
+            if child_tag.is_element() && !MODELED_STAFF_DETAILS_TAGS.contains(&child_tag.name.local_name.as_str()) {
+                details._xml_tags.push(child_tag.clone());
+            }
+        }
+        details._xml_tags.extend(xml_tag.anchored_comments());
+        Ok((xml_tag.try_get_attribute_as("number")?.unwrap_or(1), details))
+    }
+
+    pub fn to_xml_tag(&self, staff: Option<u8>) -> XmlTag {
+        let mut details = XmlTag::new("staff-details");
+        if let Some(staff) = staff {
+            details.add_attribute("number", staff.to_string());
+        }
+        if let Some(staff_type) = self.staff_type.as_ref() {
+            details.add_tag("staff-type").add_text(staff_type);
+        }
+        if let Some(staff_lines) = self.staff_lines {
+            details.add_tag("staff-lines").add_text(staff_lines.to_string());
+        }
+        for tag in self._xml_tags.iter().filter(|tag| tag.is_element()) {
+            details.direct_add_tag(tag.clone());
+        }
+        details.order_by_schema();
+        details.restore_comments(self._xml_tags.iter().filter(|tag| !tag.is_element()));
+        details
+    }
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone, Default, PartialEq)]
 pub struct attributes {
-    pub divisions: Option<u8>,
-    pub key: Option<i8>,
+    pub divisions: Option<Divisions>,
+    pub key: Option<KeySignature>,
     pub time: Option<TimeSignature>,
-    pub clef: Option<Clef>,
-    pub staves: Option<u8>
+    pub clefs: BTreeMap< u8, Clef>, // by staff number, counted from 1 at the top
+    pub staves: Option<u8>,
+    pub staff_details: BTreeMap< u8, StaffDetails>
 }
 
 impl attributes {
     pub fn from_xml_tag(xml_tag: &XmlTag) -> attributes {
-        assert_eq!(xml_tag.name.local_name, "attributes", "Tag is not <attributes>");
-        attributes {
-            divisions: xml_tag.get_tag_content_as::<u8>("divisions"),
-            key: xml_tag.get_tag_content_as("key/fifths"),
-            time: {
-                xml_tag.search_path_unique("time")
-                    .and(Some(
-                            (
-                                xml_tag.get_tag_content_as("time/beats").unwrap(),
-                                xml_tag.get_tag_content_as("time/beat-type").unwrap()
-                            )
-                        )
-                    )
-            },
+        Self::try_from_xml_tag(xml_tag).unwrap()
+    }
 
-            clef: {
-                xml_tag.search_path_unique("clef")
-                    .and(Some(
-                        Clef {
-                            sign: ClefSign::from_str(
-                                xml_tag.get_tag_content("clef/sign")
-                                    .unwrap()
-                                    .as_ref()
-                            ),
-                            line: xml_tag.get_tag_content_as("clef/line").unwrap()
-                        }
-                    ))
+    pub fn try_from_xml_tag(xml_tag: &XmlTag) -> MashResult<attributes> {
+        if xml_tag.name.local_name != "attributes" {
+            return Err(MashError::UnexpectedTag {
+                expected: "attributes".to_string(), location: Location::of(xml_tag)
+            });
+        }
+        let mut clefs = BTreeMap::new();
+        for clef_tag in xml_tag.search_path("clef") {
+            let (staff, clef) = Clef::try_from_xml_tag(clef_tag).map_err(|err| err.within(xml_tag))?;
+            clefs.insert(staff, clef);
+        }
+        let mut staff_details = BTreeMap::new();
+        for details_tag in xml_tag.search_path("staff-details") {
+            let (staff, details) = StaffDetails::try_from_xml_tag(details_tag).map_err(|err| err.within(xml_tag))?;
+            staff_details.insert(staff, details);
+        }
+        Ok(attributes {
+            // an xs:decimal above 0
+            divisions: match xml_tag.try_get_tag_content("divisions")? {
+                Some(text) => match Frac::from_decimal(text.trim()) {
+                    Some(divisions) if divisions.signum() > 0 => Some(divisions),
+                    _ => return Err(MashError::InvalidValue {
+                        value: text, location: Location::of_path(xml_tag, "divisions")
+                    })
+                },
+                None => None
             },
+            key: match xml_tag.search_path_unique("key") {
+                Some(key_tag) => Some(KeySignature::try_from_xml_tag(key_tag).map_err(|err| err.within(xml_tag))?),
+                None => None
+            },
+            time: match xml_tag.search_path_unique("time") {
+                Some(time_tag) => Some(TimeSignature::try_from_xml_tag(time_tag).map_err(|err| err.within(xml_tag))?),
+                None => None
+            },
+            clefs,
+            staves: xml_tag.try_get_tag_content_as("staves")?,
+            staff_details
+        })
+    }
+
+    /// Clef of staff, staves counted from 1 at the top
+    pub fn clef(&self, staff: u8) -> Option<&Clef> {
+        self.clefs.get(&staff)
+    }
+
+    /// Divisions in effect for context, e.g. a <note> whose <duration> is being read
+    pub fn require_divisions(&self, context: &XmlTag) -> MashResult<Divisions> {
+        self.divisions.clone()
+            .ok_or_else(|| MashError::MissingTag {
+                location: Location {
+                    path: vec!["attributes".to_string(), "divisions".to_string()],
+                    position: context.position
+                }
+            })
+    }
 
-            staves: {
-                xml_tag.search_path_unique("staves")
-                    .and_then(|tag| Some(tag.text.as_ref().unwrap().parse().unwrap()))
+    /// Divisions to write <duration> with, an error when no <attributes> set them
+    pub fn require_divisions_to_write(&self) -> MashResult<Divisions> {
+        self.divisions.clone().ok_or_else(|| MashError::MissingTag {
+            location: Location {
+                path: vec!["attributes".to_string(), "divisions".to_string()],
+                position: None
             }
+        })
+    }
+
+    /// The <duration> of xml_tag, e.g. a <note> or <backup>, in quarter notes. Its
+    /// content is an xs:decimal count of divisions
+    pub fn read_duration(&self, xml_tag: &XmlTag) -> MashResult<Duration> {
+        let text: String = xml_tag.require_tag_content_as("duration")?;
+        let count = Frac::from_decimal(text.trim())
+            .filter(|count| count.signum() >= 0)
+            .ok_or_else(|| MashError::InvalidValue {
+                value: text.clone(), location: Location::of_path(xml_tag, "duration")
+            })?;
+        Ok(count / self.require_divisions(xml_tag)?)
+    }
+
+    /// Overwrite the fields of self with the ones present in newer.
+    /// <attributes> in later measures only list what changed, so this is used to
+    /// carry the current attributes forward from measure to measure
+    pub fn update(&mut self, newer: attributes) {
+        if newer.divisions.is_some() { self.divisions = newer.divisions; }
+        if newer.key.is_some() { self.key = newer.key; }
+        if newer.time.is_some() { self.time = newer.time; }
+        if newer.staves.is_some() { self.staves = newer.staves; }
+        // a change of clef or staff details only touches the staves it numbers
+        self.clefs.extend(newer.clefs);
+        self.staff_details.extend(newer.staff_details);
+    }
+
+    /// The fields of self that current does not already have, e.g. to leave out
+    /// a clef restated with the value it had
+    pub fn changed_from(&self, current: &attributes) -> attributes {
+        fn changed<T: Clone + PartialEq>(newer: &Option<T>, current: &Option<T>) -> Option<T> {
+            newer.as_ref().filter(|newer| current.as_ref() != Some(*newer)).cloned()
+        }
+        fn changed_staves<T: Clone + PartialEq>(newer: &BTreeMap< u8, T>, current: &BTreeMap< u8, T>) -> BTreeMap< u8, T> {
+            newer.iter()
+                .filter(|(staff, value)| current.get(staff) != Some(*value))
+                .map(|(staff, value)| (*staff, value.clone()))
+                .collect()
+        }
+        attributes {
+            divisions: changed(&self.divisions, &current.divisions),
+            key: changed(&self.key, &current.key),
+            time: changed(&self.time, &current.time),
+            clefs: changed_staves(&self.clefs, &current.clefs),
+            staves: changed(&self.staves, &current.staves),
+            staff_details: changed_staves(&self.staff_details, &current.staff_details)
         }
     }
 
-    fn to_xml_tag(&self) -> XmlTag {
+    /// Whether no field is set
+    pub fn is_empty(&self) -> bool {
+        *self == attributes::default()
+    }
+
+    /// Remove from attributes_tag the children modeled by this struct, leaving the
+    /// ones it does not model such as <transpose>. Only the first <key> and <time>
+    /// are read, so any other, e.g. numbered for one staff, is left too
+    pub fn retain_unmodeled(attributes_tag: &mut XmlTag) {
+        let mut read = HashSet::new();
+        attributes_tag.retain_children(|child| match child.name.local_name.as_str() {
+            "divisions" | "staves" | "clef" | "staff-details" => false,
+            name @ "key" | name @ "time" => !read.insert(name.to_string()),
+            _ => true
+        });
+    }
+
+    /// Err when divisions has no exact decimal form, e.g. 1/3
+    pub fn to_xml_tag(&self) -> MashResult<XmlTag> {
         let mut builder = XmlTag::new_tag_builder();
         let attr = builder.add_tag("attributes");
         // <divisions>
-        if self.divisions.is_some() {
-            attr.add_tag("divisions").add_text(self.divisions.unwrap().to_string());
+        if let Some(divisions) = self.divisions.as_ref() {
+            let text = divisions.to_decimal().ok_or_else(|| MashError::InvalidValue {
+                value: divisions.to_string(),
+                location: Location { path: vec!["attributes".to_string(), "divisions".to_string()], position: None }
+            })?;
+            attr.add_tag("divisions").add_text(text);
         }
         // <key>
-        if self.key.is_some() {
-            attr.add_tag("key").add_tag("fifths").add_text("0");
+        if let Some(key) = self.key.as_ref() {
+            attr.direct_add_tag(key.to_xml_tag());
         }
         // <time>
-        if self.time.is_some() {
-            let time = attr.add_tag("time");
-            time.add_tag("beats").add_text(self.time.unwrap().0.to_string());
-            time.add_tag("beat-type").add_text(self.time.unwrap().1.to_string());
-        }
-        // <clef>
-        if self.clef.is_some() {
-            let clef = attr.add_tag("clef");
-            clef.add_tag("sign")
-                .add_text(self.clef.as_ref().unwrap()
-                    .sign.to_str()
-                );
-
-            clef.add_tag("line")
-                .add_text(self.clef.as_ref().unwrap()
-                    .line.to_string()
-                );
+        if let Some(time) = self.time.as_ref() {
+            attr.direct_add_tag(time.to_xml_tag());
         }
         // <staves>
         if self.staves.is_some() {
             attr.add_tag("staves").add_text(self.staves.unwrap().to_string());
         }
-        builder.built_tag()
+        // <clef>, numbered unless the part has a single staff
+        let numbered = matches!(self.staves, Some(staves) if staves > 1)
+            || self.clefs.keys().any(|staff| *staff != 1);
+        for (staff, clef) in self.clefs.iter() {
+            attr.direct_add_tag(clef.to_xml_tag(if numbered { Some(*staff) } else { None }));
+        }
+        // <staff-details>
+        for (staff, details) in self.staff_details.iter() {
+            attr.direct_add_tag(details.to_xml_tag(if numbered { Some(*staff) } else { None }));
+        }
+        Ok(builder.built_tag())
     }
 }
 
@@ -132,7 +793,166 @@ mod tests {
         let xml_tag = XmlTag::from_buffer(attr_xml());
         let attr = attributes::from_xml_tag(&xml_tag);
         println!("{:#?}", attr);
-        attr.to_xml_tag().print_debug(0);
+        attr.to_xml_tag().unwrap().print_debug(0);
+    }
+
+    #[test]
+    fn test_unknown_clef () {
+        let xml_tag = XmlTag::from_buffer(r#"<attributes>
+        <clef>
+          <sign>X</sign>
+          </clef>
+        </attributes>"#.as_bytes());
+        let err = attributes::try_from_xml_tag(&xml_tag).unwrap_err();
+        assert!(err.to_string().starts_with("invalid value \"X\" in attributes/clef/sign (line 2,"));
+    }
+
+    #[test]
+    fn test_time () {
+        let time_of = |xml: &str| TimeSignature::try_from_xml_tag(&XmlTag::from_buffer(xml.as_bytes()));
+        let common = time_of(r#"<time symbol="common"><beats>4</beats><beat-type>4</beat-type></time>"#).unwrap();
+        assert_eq!(common.symbol, Some(TimeSymbol::Common));
+        assert_eq!(common.bar_duration(), Some(Frac::from(4u32)));
+        assert_eq!(common.kind(), MeterKind::Simple);
+        assert_eq!(common.beat_groups(), vec![Frac::from(1u32); 4]);
+
+        let six_eight = TimeSignature::new(6, 8);
+        assert_eq!(six_eight.kind(), MeterKind::Compound);
+        assert_eq!(six_eight.beat_groups(), vec![Frac::new(3, 2); 2]);
+        assert_eq!(TimeSignature::new(3, 8).kind(), MeterKind::Simple);
+
+        let additive = time_of("<time><beats>3+2</beats><beat-type>8</beat-type></time>").unwrap();
+        assert_eq!(additive.beats[0].beats, vec![3, 2]);
+        assert_eq!(additive.bar_duration(), Some(Frac::new(5, 2)));
+        assert_eq!(additive.kind(), MeterKind::Additive);
+        assert_eq!(additive.beat_groups(), vec![Frac::new(3, 2), Frac::from(1u32)]);
+
+        // 2/4 followed by 3/8 in every bar
+        let composite = time_of("<time><beats>2</beats><beat-type>4</beat-type>\
+                                 <beats>3</beats><beat-type>8</beat-type></time>").unwrap();
+        assert_eq!(composite.bar_duration(), Some(Frac::new(7, 2)));
+        assert_eq!(composite.beat_groups(), vec![Frac::from(2u32), Frac::new(3, 2)]);
+
+        let free = time_of("<time print-object=\"no\"><senza-misura/></time>").unwrap();
+        assert_eq!(free.senza_misura, Some(String::new()));
+        assert_eq!(free.bar_duration(), None);
+
+        for time in [common, additive, composite, free].iter() {
+            assert_eq!(&TimeSignature::from_xml_tag(&time.to_xml_tag()), time);
+        }
+        assert!(matches!(time_of("<time><beats>3</beats></time>"), Err(MashError::MissingTag { .. })));
+        assert!(matches!(time_of("<time><beats>3x</beats><beat-type>4</beat-type></time>"),
+                         Err(MashError::InvalidValue { .. })));
+        // a beat type of 0 or bars of no beats have no length
+        for xml in ["<time><beats>0</beats><beat-type>0</beat-type></time>",
+                    "<time><beats>3</beats><beat-type>0</beat-type></time>",
+                    "<time><beats>0+0</beats><beat-type>4</beat-type></time>"].iter() {
+            assert!(matches!(time_of(xml), Err(MashError::InvalidValue { .. })), "{}", xml);
+        }
+        assert_eq!(TimeSignature::new(0, 0).bar_duration(), None);
+    }
+
+    #[test]
+    fn test_key () {
+        let attr = attributes::from_xml_tag(&XmlTag::from_buffer(r#"<attributes>
+        <key print-object="yes">
+          <cancel>2</cancel>
+          <fifths>-3</fifths>
+          <mode>minor</mode>
+          </key>
+        </attributes>"#.as_bytes()));
+        let key = attr.key.as_ref().unwrap();
+        assert_eq!(key.cancel, Some(2));
+        assert_eq!(key.alter_of(&Step::B), -1);
+        assert_eq!(key.alter_of(&Step::A), -1);
+        assert_eq!(key.alter_of(&Step::D), 0);
+        assert_eq!(key.tonic(), Some((Step::C, 0)));
+        assert_eq!(attr.to_xml_tag().unwrap().get_tag_content("key/fifths"), Some("-3".to_string()));
+        assert_eq!(attributes::from_xml_tag(&attr.to_xml_tag().unwrap()), attr);
+
+        assert_eq!(KeySignature::new(2, None).tonic(), Some((Step::D, 0)));
+        assert_eq!(KeySignature::new(3, Some(KeyMode::Minor)).tonic(), Some((Step::F, 1)));
+        assert_eq!(KeySignature::new(-2, Some(KeyMode::Dorian)).tonic(), Some((Step::C, 0)));
+        assert_eq!(KeySignature::new(-6, None).tonic(), Some((Step::G, -1)));
+        assert_eq!(KeySignature::new(0, Some(KeyMode::None)).tonic(), None);
+        // past 7 sharps the F gets a double sharp
+        assert_eq!(KeySignature::new(8, None).alter_of(&Step::F), 2);
+        assert_eq!(KeySignature::new(8, None).alter_of(&Step::C), 1);
+        // the ends of <fifths>
+        assert_eq!(KeySignature::new(127, Some(KeyMode::Locrian)).tonic(), Some((Step::F, 19)));
+        assert_eq!(KeySignature::new(-128, None).tonic(), Some((Step::B, -19)));
+        assert_eq!(KeySignature::new(-128, None).alter_of(&Step::B), -19);
+        assert_eq!(KeySignature::new(127, None).alter_of(&Step::F), 19);
+
+        // non-traditional: B flat, E flat and F sharp
+        let key_tag = XmlTag::from_buffer(r#"<key>
+          <key-step>B</key-step>
+          <key-alter>-1</key-alter>
+          <key-step>E</key-step>
+          <key-alter>-1</key-alter>
+          <key-accidental>flat</key-accidental>
+          <key-step>F</key-step>
+          <key-alter>1</key-alter>
+          <key-octave number="1">4</key-octave>
+          </key>"#.as_bytes());
+        let key = KeySignature::from_xml_tag(&key_tag);
+        assert_eq!(key.alter_of(&Step::E), -1);
+        assert_eq!(key.alter_of(&Step::F), 1);
+        assert_eq!(key.alter_of(&Step::C), 0);
+        assert_eq!(key.tonic(), None);
+        let written = key.to_xml_tag();
+        let names: Vec< &str> = written.child_tags.iter().map(|tag| tag.name.local_name.as_str()).collect();
+        assert_eq!(names, vec!["key-step", "key-alter", "key-step", "key-alter", "key-accidental",
+                               "key-step", "key-alter", "key-octave"]);
+        assert_eq!(KeySignature::from_xml_tag(&written), key);
+    }
+
+    #[test]
+    fn test_clefs () {
+        let xml_tag = XmlTag::from_buffer(r#"<attributes>
+        <staves>3</staves>
+        <clef number="1">
+          <sign>G</sign>
+          <line>2</line>
+          <clef-octave-change>-1</clef-octave-change>
+          </clef>
+        <clef number="2">
+          <sign>percussion</sign>
+          </clef>
+        <clef number="3">
+          <sign>TAB</sign>
+          <line>5</line>
+          </clef>
+        <staff-details number="2">
+          <staff-lines>1</staff-lines>
+          </staff-details>
+        <staff-details number="3">
+          <staff-lines>6</staff-lines>
+          <staff-tuning line="1">
+            <tuning-step>E</tuning-step>
+            <tuning-octave>2</tuning-octave>
+            </staff-tuning>
+          </staff-details>
+        </attributes>"#.as_bytes());
+        let attr = attributes::from_xml_tag(&xml_tag);
+        assert_eq!(attr.clef(1).unwrap().octave_change, Some(-1));
+        assert_eq!(attr.clef(2).unwrap().line, None);
+        assert!(!attr.clef(2).unwrap().is_pitched());
+        assert_eq!(attr.clef(3).unwrap().sign, ClefSign::Tab);
+        assert_eq!(attr.staff_details[&2].staff_lines, Some(1));
+        assert_eq!(attr.staff_details[&3].staff_lines, Some(6));
+        assert_eq!(attributes::from_xml_tag(&attr.to_xml_tag().unwrap()), attr);
+
+        // a later clef change only replaces the clef of the staff it numbers
+        let mut current = attr.clone();
+        current.update(attributes::from_xml_tag(&XmlTag::from_buffer(r#"<attributes>
+        <clef number="2">
+          <sign>none</sign>
+          </clef>
+        </attributes>"#.as_bytes())));
+        assert_eq!(current.clef(1), attr.clef(1));
+        assert_eq!(current.clef(2).unwrap().sign, ClefSign::None);
+        assert_eq!(current.staff_details, attr.staff_details);
     }
 
     fn attr_xml () -> &'static [u8] {
@@ -7,12 +7,16 @@ use std::ops::Deref;
 use xml::attribute::OwnedAttribute;
 use crate::msc::attributes::attributes;
 use crate::libs::frac::Frac;
-use crate::msc::gnote::note_attr::{Tie, Lyric, Pitch, Accidental, TimeModification};
+use crate::libs::error::{MashError, MashResult, Location};
+use crate::msc::gnote::note_attr::{Tie, Lyric, Pitch, Accidental, TimeModification, Notation};
 
 pub mod note_attr {
+    use std::convert::TryFrom;
     use std::collections::HashMap;
     use crate::libs::frac::Frac;
+    use crate::libs::error::{MashError, MashResult, Location};
     use crate::parser::xml_tag::XmlTag;
+    use xml::attribute::OwnedAttribute;
 
     #[derive(Debug, Eq, PartialEq, Hash, Clone)]
     pub enum Accidental {
@@ -21,21 +25,38 @@ pub mod note_attr {
         flat,
         dflat,
         natural,
+        // any other accidental-value as written, e.g. quarter-sharp, sharp-sharp or sori
+        other(String),
     }
     impl Accidental {
         pub fn from_xml_tag(accidental_tag: &XmlTag) -> Self {
-            match accidental_tag.text.as_ref().unwrap().as_str() {
-                "sharp" => Accidental::sharp,
-                "double-sharp" => Accidental::dsharp,
-                "flat" => Accidental::flat,
-                "flat-flat" => Accidental::dflat,
-                "natural" => Accidental::natural,
-                _ => unreachable!()
+            Self::try_from_xml_tag(accidental_tag).unwrap()
+        }
+
+        pub fn try_from_xml_tag(accidental_tag: &XmlTag) -> MashResult<Self> {
+            match accidental_tag.require_text()? {
+                "sharp" => Ok(Accidental::sharp),
+                "double-sharp" => Ok(Accidental::dsharp),
+                "flat" => Ok(Accidental::flat),
+                "flat-flat" => Ok(Accidental::dflat),
+                "natural" => Ok(Accidental::natural),
+                other => Ok(Accidental::other(other.to_string()))
             }
         }
 
         pub fn to_xml_tag(&self) -> XmlTag {
-            todo!()
+            let mut builder = XmlTag::new_tag_builder();
+            builder.add_tag("accidental").add_text({
+                match self {
+                    Accidental::sharp => "sharp",
+                    Accidental::dsharp => "double-sharp",
+                    Accidental::flat => "flat",
+                    Accidental::dflat => "flat-flat",
+                    Accidental::natural => "natural",
+                    Accidental::other(other) => other,
+                }
+            });
+            builder.built_tag()
         }
     }
 
@@ -49,6 +70,33 @@ pub mod note_attr {
         F,
         G
     }
+    impl Step {
+        /// The error has no location, callers add the one of the tag read from
+        pub fn try_from_str(step: &str) -> MashResult<Self> {
+            match step {
+                "A" => Ok(Step::A),
+                "B" => Ok(Step::B),
+                "C" => Ok(Step::C),
+                "D" => Ok(Step::D),
+                "E" => Ok(Step::E),
+                "F" => Ok(Step::F),
+                "G" => Ok(Step::G),
+                other => Err(MashError::InvalidValue { value: other.to_string(), location: Location::default() })
+            }
+        }
+
+        pub fn to_str(&self) -> &str {
+            match self {
+                Step::A => "A",
+                Step::B => "B",
+                Step::C => "C",
+                Step::D => "D",
+                Step::E => "E",
+                Step::F => "F",
+                Step::G => "G",
+            }
+        }
+    }
 
     #[derive(Debug, Eq, PartialEq, Hash, Clone)]
     pub struct Pitch {
@@ -58,48 +106,32 @@ pub mod note_attr {
     }
     impl Pitch {
         pub fn from_xml_tag(pitch_tag: &XmlTag) -> Self {
-            Self {
-                step: match pitch_tag
-                    .search_path_unique("step").unwrap()
-                    .text.as_ref().unwrap()
-                    .as_str()
-                {
-                    "A" => Step::A,
-                    "B" => Step::B,
-                    "C" => Step::C,
-                    "D" => Step::D,
-                    "E" => Step::E,
-                    "F" => Step::F,
-                    "G" => Step::G,
-                    _ => unreachable!()
-                },
-                octave: pitch_tag.get_tag_content_as("octave").unwrap(),
-                alter: pitch_tag.search_path_unique("alter")
-                    .and_then(|x| x.text.as_ref().unwrap().parse::<i8>().ok())
-            }
+            Self::try_from_xml_tag(pitch_tag).unwrap()
+        }
+
+        pub fn try_from_xml_tag(pitch_tag: &XmlTag) -> MashResult<Self> {
+            let step_tag = pitch_tag.require_tag("step")?;
+            let step = step_tag.require_text().map_err(|err| err.within(pitch_tag))?;
+            Ok(Self {
+                step: Step::try_from_str(step).map_err(|_| MashError::InvalidValue {
+                    value: step.to_string(), location: Location::of(step_tag)
+                }.within(pitch_tag))?,
+                octave: pitch_tag.require_tag_content_as("octave")?,
+                alter: pitch_tag.try_get_tag_content_as("alter")?
+            })
         }
 
         pub fn to_xml_tag(&self) -> XmlTag {
             let mut builder = XmlTag::new_tag_builder();
             let pitch = builder.add_tag("pitch");
             // step
-            pitch.add_tag("step").add_text({
-                match self.step {
-                    Step::A => "A",
-                    Step::B => "B",
-                    Step::C => "C",
-                    Step::D => "D",
-                    Step::E => "E",
-                    Step::F => "F",
-                    Step::G => "G",
-                }
-            });
-            // octave
-            pitch.add_tag("octave").add_text(self.octave.to_string().as_str());
+            pitch.add_tag("step").add_text(self.step.to_str());
             // alter
             if self.alter.is_some() {
                 pitch.add_tag("alter").add_text(self.alter.unwrap().to_string().as_str());
             }
+            // octave
+            pitch.add_tag("octave").add_text(self.octave.to_string().as_str());
             builder.built_tag()
         }
     }
@@ -112,28 +144,31 @@ pub mod note_attr {
     impl Tie {
         pub fn from_xml_tag(tie_tag: &XmlTag) -> Self
         {
-            match tie_tag.get_attribute_value("type").unwrap().as_str()
+            Self::try_from_xml_tag(tie_tag).unwrap()
+        }
+
+        pub fn try_from_xml_tag(tie_tag: &XmlTag) -> MashResult<Self>
+        {
+            match tie_tag.require_attribute_value("type")?.as_str()
             {
-                "start" => Self::start,
-                "end" => Self::stop,
-                _ => unreachable!()
+                "start" => Ok(Self::start),
+                "stop" => Ok(Self::stop),
+                other => Err(MashError::InvalidValue {
+                    value: other.to_string(), location: Location::of_path(tie_tag, "@type")
+                })
             }
         }
 
-        /// Returns two necessary tags <tie> and <notations>
+        /// Returns <tie>. The matching <notations><tied> is kept with the other
+        /// notations of the note
         pub fn to_xml_tag(&self) -> XmlTag
         {
             let variant_str = match self {
                 Self::start => "start",
                 Self::stop => "stop",
-                _ => unreachable!()
             };
             let mut builder = XmlTag::new_tag_builder();
-
-            let mut note_tag = builder.add_tag("note");
-            note_tag.add_tag("tie").add_attribute("type", variant_str);
-            note_tag.add_tag("notations").add_tag("tied").add_attribute("type", variant_str);
-
+            builder.add_tag("tie").add_attribute("type", variant_str);
             builder.built_tag()
         }
     }
@@ -144,20 +179,30 @@ pub mod note_attr {
         normal_notes: u8,
     }
     impl TimeModification {
+        /// actual_notes in the time of normal_notes, e.g. 3, 2 for a triplet
+        pub fn new(actual_notes: u8, normal_notes: u8) -> Self {
+            Self { actual_notes, normal_notes }
+        }
+
+        /// Factor applied to the written duration, normal_notes/actual_notes
+        pub fn ratio(&self) -> Frac {
+            Frac::new(self.normal_notes as i64, self.actual_notes as i64)
+        }
+
         pub fn from_xml_tag(xml_tag: &XmlTag) -> Self {
-            Self {
-                actual_notes: xml_tag.search_path_unique("actual-notes").unwrap()
-                    .text.as_ref().unwrap()
-                    .parse().unwrap(),
-                normal_notes: xml_tag.search_path_unique("normal-notes").unwrap()
-                    .text.as_ref().unwrap()
-                    .parse().unwrap()
-            }
+            Self::try_from_xml_tag(xml_tag).unwrap()
+        }
+
+        pub fn try_from_xml_tag(xml_tag: &XmlTag) -> MashResult<Self> {
+            Ok(Self {
+                actual_notes: xml_tag.require_tag_content_as("actual-notes")?,
+                normal_notes: xml_tag.require_tag_content_as("normal-notes")?
+            })
         }
 
         pub fn to_xml_tag(&self) -> XmlTag {
             let mut builder = XmlTag::new_tag_builder();
-            let mut time_mod_tag = builder.add_tag("time-modification");
+            let time_mod_tag = builder.add_tag("time-modification");
             time_mod_tag.add_tag("actual-notes").add_text(self.actual_notes.to_string().as_str());
             time_mod_tag.add_tag("normal-notes").add_text(self.normal_notes.to_string().as_str());
             builder.built_tag()
@@ -165,6 +210,23 @@ pub mod note_attr {
     }
 
     pub type Duration = Frac;
+    /// Divisions per quarter note, an xs:decimal. Exporters often use 480 or more,
+    /// and a few write decimals such as 2.5
+    pub type Divisions = Frac;
+
+    /// Express duration as a count of divisions, the decimal content of <duration>.
+    /// Err when that count is negative or has no exact decimal form, e.g. a triplet
+    /// eighth in 2.5 divisions. The error is located at <duration>, callers add the
+    /// tag it is written in
+    pub fn duration_to_divisions(duration: &Duration, divisions: &Divisions) -> MashResult<String> {
+        let count = duration * divisions;
+        count.to_decimal()
+            .filter(|_| count.signum() >= 0)
+            .ok_or_else(|| MashError::InvalidValue {
+                value: count.to_string(),
+                location: Location { path: vec!["duration".to_string()], position: None }
+            })
+    }
 
     pub type LengthType = String;
     lazy_static! {
@@ -184,6 +246,137 @@ pub mod note_attr {
         };
     }
 
+    /// Duration of an undotted length_type, None for types missing from LENGTH_TYPE_TABL
+    pub fn length_type_duration(length_type: &str) -> Option<Duration> {
+        // the table counts notes per whole, durations are in quarters
+        LENGTH_TYPE_TABL.get(length_type).map(|per_whole| Frac::new(4, *per_whole as i64))
+    }
+
+    // dots are only written while the last one stays at least a 512th
+    const SHORTEST_PER_WHOLE: u16 = 512;
+    const MAX_DOTS: u8 = 3;
+
+    /// How a duration is written: <type>, <dot>s and <time-modification>
+    #[derive(Debug, Eq, PartialEq, Hash, Clone)]
+    pub struct Notation {
+        pub length_type: LengthType,
+        pub dot: u8,
+        pub time_mod: Option<TimeModification>,
+    }
+    impl Notation {
+        /// The duration written by self, None if length_type is not in LENGTH_TYPE_TABL
+        pub fn duration(&self) -> Option<Duration> {
+            let written = Self::dotted(&length_type_duration(&self.length_type)?, self.dot);
+            Some(match &self.time_mod {
+                Some(time_mod) => written * time_mod.ratio(),
+                None => written
+            })
+        }
+
+        /// The single note value that shows duration. Plain and dotted values are
+        /// preferred over tuplets. Tuplets are only put on undotted values, as p:q
+        /// with q the largest power of two below p, e.g. 3:2, 5:4 or 7:4.
+        /// None if no single note can show duration, see tied_from_duration
+        pub fn from_duration(duration: &Duration) -> Option<Self> {
+            if !Self::is_writable(duration) {
+                return None;
+            }
+            let candidates = Self::plain_values();
+            if let Some((length_type, dot, _)) = candidates.iter().find(|(_, _, value)| value == duration) {
+                return Some(Notation { length_type: length_type.to_string(), dot: *dot, time_mod: None });
+            }
+            candidates.iter().filter(|(_, dot, _)| *dot == 0).find_map(|(length_type, dot, value)| {
+                let ratio = duration / value;
+                let time_mod = Self::tuplet_of(ratio.denom())?;
+                if time_mod.ratio() != ratio {
+                    return None;
+                }
+                Some(Notation { length_type: length_type.to_string(), dot: *dot, time_mod: Some(time_mod) })
+            })
+        }
+
+        /// Notes to tie together to show duration, longest first. A single note when
+        /// from_duration finds one. Otherwise the odd part of the denominator of
+        /// duration picks the tuplet shared by every note, and what is left is split
+        /// greedily into plain and dotted values, e.g. 5 quarters give a whole and a
+        /// quarter, 5/6 of a quarter a triplet quarter and a triplet 16th.
+        /// None if duration is not positive, needs notes shorter than a 512th, or
+        /// a tuplet of more than 255 notes
+        pub fn tied_from_duration(duration: &Duration) -> Option<Vec<Self>> {
+            if let Some(notation) = Self::from_duration(duration) {
+                return Some(vec![notation]);
+            }
+            if !Self::is_writable(duration) {
+                return None;
+            }
+            let mut odd = duration.denom();
+            while odd % 2 == 0 {
+                odd /= 2;
+            }
+            let time_mod = match odd {
+                1 => None,
+                _ => Some(Self::tuplet_of(odd)?)
+            };
+            // the written duration, before the tuplet scales it
+            let mut rest = match &time_mod {
+                Some(time_mod) => duration / time_mod.ratio(),
+                None => duration.clone()
+            };
+            let candidates = Self::plain_values();
+            let mut notations = Vec::new();
+            while rest > Frac::from(0u32) {
+                let (length_type, dot, value) = candidates.iter()
+                    .filter(|(_, _, value)| *value <= rest)
+                    .max_by(|a, b| a.2.cmp(&b.2))?;
+                notations.push(Notation {
+                    length_type: length_type.to_string(), dot: *dot, time_mod: time_mod.clone()
+                });
+                rest -= value;
+            }
+            Some(notations)
+        }
+
+        fn is_writable(duration: &Duration) -> bool {
+            duration.is_finite() && *duration > Frac::from(0u32)
+        }
+
+        /// value * (2 - 1/2^dot)
+        fn dotted(value: &Duration, dot: u8) -> Duration {
+            let halves = 1i64 << dot;
+            value * Frac::new(2 * halves - 1, halves)
+        }
+
+        /// Every undotted and dotted value no shorter than a 512th, as
+        /// (type, dots, duration), fewest dots first and then longest first
+        fn plain_values() -> Vec< (&'static str, u8, Duration)> {
+            let mut types: Vec< (&'static str, u16)> = LENGTH_TYPE_TABL.iter()
+                .map(|(length_type, per_whole)| (*length_type, *per_whole))
+                .collect();
+            types.sort_by_key(|(_, per_whole)| *per_whole);
+            let mut values = Vec::new();
+            for dot in 0..=MAX_DOTS {
+                for (length_type, per_whole) in types.iter() {
+                    if per_whole << dot <= SHORTEST_PER_WHOLE {
+                        values.push((*length_type, dot, Self::dotted(&Frac::new(4, *per_whole as i64), dot)));
+                    }
+                }
+            }
+            values
+        }
+
+        /// The p:q tuplet for an odd p, with q the largest power of two below p
+        fn tuplet_of(actual_notes: i64) -> Option<TimeModification> {
+            if actual_notes % 2 == 0 || actual_notes < 3 || actual_notes > u8::MAX as i64 {
+                return None;
+            }
+            let mut normal_notes = 1;
+            while normal_notes * 2 < actual_notes {
+                normal_notes *= 2;
+            }
+            Some(TimeModification::new(actual_notes as u8, normal_notes as u8))
+        }
+    }
+
     pub type Offset = Frac;
     pub type Octave = u8;
 
@@ -192,40 +385,117 @@ pub mod note_attr {
 
     #[derive(Debug, Eq, PartialEq, Hash, Clone)]
     pub struct Lyric {
-        syllabic: Syllabic,
-        text: String,
-        number: u8, // used to specify which line the lyric is on
+        // attributes that we dont care about, e.g. name, default-y, justify
+        _xml_attrs: Vec<OwnedAttribute>,
+        // children after the first <syllabic> and <text>, in order, e.g. <elision>,
+        // the <syllabic> and <text> that follow it, <extend>. Then its comments
+        _xml_tags: Vec< XmlTag>,
+        syllabic: Option<Syllabic>, // a missing <syllabic> reads as single
+        text: Option<String>, // lyrics made only of <extend/>, <humming/>... have no <text>
+        number: Option<String>, // an NMTOKEN naming the line the lyric is on, e.g. "1" or "verse1"
     }
     impl Lyric {
         pub fn from_xml_tag(lyric_tag: &XmlTag) -> Self {
-            Self
-            {
-                syllabic: match lyric_tag.get_tag_content("syllabic")
-                    .unwrap().as_str()
-                {
-                    "single" => Syllabic::single,
-                    "begin" => Syllabic::begin,
-                    "end" => Syllabic::end,
-                    "middle" => Syllabic::middle,
-                    _ => unreachable!()
-                },
-
-                text: lyric_tag.get_tag_content("text").as_ref().unwrap().clone(),
-
-                number: lyric_tag.get_attribute_as("number").or(Some(1)).unwrap()
+            Self::try_from_xml_tag(lyric_tag).unwrap()
+        }
+
+        pub fn try_from_xml_tag(lyric_tag: &XmlTag) -> MashResult<Self> {
+            let mut syllabic = None;
+            let mut text = None;
+            let mut xml_tags = Vec::new();
+            for child in lyric_tag.child_tags.iter() {
+                match child.name.local_name.as_str() {
+                    // a <syllabic> after the first <text> belongs to an elided syllable
+                    "syllabic" if syllabic.is_none() && text.is_none() => {
+                        syllabic = Some(match child.text.as_deref().unwrap_or_default() {
+                            "single" => Syllabic::single,
+                            "begin" => Syllabic::begin,
+                            "end" => Syllabic::end,
+                            "middle" => Syllabic::middle,
+                            other => return Err(MashError::InvalidValue {
+                                value: other.to_string(), location: Location::of_path(lyric_tag, "syllabic")
+                            })
+                        });
+                    }
+                    "text" if text.is_none() => {
+                        text = Some(child.text.clone().unwrap_or_default());
+                    }
+                    _ => if child.is_element() {
+                        xml_tags.push(child.clone());
+                    }
+                }
             }
+            xml_tags.extend(lyric_tag.anchored_comments());
+            Ok(Self {
+                _xml_attrs: lyric_tag.attributes.iter()
+                    .filter(|attr| attr.name.local_name != "number")
+                    .cloned()
+                    .collect(),
+                _xml_tags: xml_tags,
+                syllabic,
+                text,
+                number: lyric_tag.get_attribute_value("number")
+            })
+        }
+
+        pub fn to_xml_tag(&self) -> XmlTag {
+            let mut builder = XmlTag::new_tag_builder();
+            let lyric = builder.add_tag("lyric");
+            if let Some(number) = self.number.as_ref() {
+                lyric.add_attribute("number", number);
+            }
+            lyric.add_attributes(&self._xml_attrs);
+            if let Some(syllabic) = self.syllabic.as_ref() {
+                lyric.add_tag("syllabic").add_text({
+                    match syllabic {
+                        Syllabic::single => "single",
+                        Syllabic::begin => "begin",
+                        Syllabic::end => "end",
+                        Syllabic::middle => "middle",
+                    }
+                });
+            }
+            if let Some(text) = self.text.as_ref() {
+                lyric.add_tag("text").add_text(text);
+            }
+            // syllabic, text, elision and extend share a rank in the schema order,
+            // so the tags after them stay where they were
+            for tag in self._xml_tags.iter().filter(|tag| tag.is_element()) {
+                lyric.direct_add_tag(tag.clone());
+            }
+            let mut lyric = builder.built_tag();
+            lyric.restore_comments(self._xml_tags.iter().filter(|tag| !tag.is_element()));
+            lyric
         }
     }
 }
-/// Represents a generalized note that could be an actual note, rest, or chord
+/// Represents a generalized note that could be an actual note, rest, or chord.
+/// A Chord holds every tone of a <chord/> run once merge_notes_in_chords has run.
+/// Straight from from_xml_tag it is a single <chord/> note, still to be merged
+/// into the note before it
 #[derive(Debug, Eq, PartialEq, Hash, Clone)]
 pub enum GnoteVariants {
     Note, Rest, Chord
 }
+
+/// What a tone of a chord after the first does not share with the others. The
+/// first tone keeps these in the fields of Gnote itself
+#[derive(Debug, Eq, PartialEq, Hash, Clone)]
+pub struct ChordTone {
+    // attributes of its <note>, e.g. default-y
+    pub _xml_attrs: Vec<OwnedAttribute>,
+    // child tags of its <note> that we dont care about, e.g. <notehead>, <notations>
+    pub _xml_tags: Vec< XmlTag>,
+    pub accidental: Option<Accidental>,
+    tie: Vec< note_attr::Tie>,
+}
 #[derive(Debug, Eq, PartialEq, Hash, Clone)]
-pub struct Gnote<'a> {
+pub struct Gnote {
     // attributes that we dont care about
-    pub _xml_attrs: &'a Vec<OwnedAttribute>,
+    pub _xml_attrs: Vec<OwnedAttribute>,
+    // child tags that we dont care about, e.g. <stem>, <beam>, <notations>, then
+    // the comments of <note>, see XmlTag::anchored_comments
+    pub _xml_tags: Vec< XmlTag>,
 
     pub variant: GnoteVariants,
     // pitch information
@@ -236,18 +506,75 @@ pub struct Gnote<'a> {
     pub duration: note_attr::Duration,
     pub time_mod: Option< note_attr::TimeModification>,
 
-    length_type: note_attr::LengthType,
+    length_type: Option< note_attr::LengthType>, // whole-measure rests may omit <type>
     dot: u8, // the number of dots in this note
-    tie: Option< note_attr::Tie>,
+    tie: Vec< note_attr::Tie>, // both start and stop in the middle of a tied run
 
     // lyrics
     pub lyrics: Vec< note_attr::Lyric>,
+
+    // <voice> and <staff>, see NoteMap::key_of for the defaults when missing
+    pub voice: Option<String>,
+    pub staff: Option<u8>,
+
+    // the tones of a merged chord after the first, one for each of pitch[1..]
+    pub chord_tones: Vec< ChordTone>,
+}
+
+/// Add or remove tie in ties, along with the <notations><tied> in xml_tags that draws it
+fn set_tie_of(ties: &mut Vec< Tie>, xml_tags: &mut Vec< XmlTag>, tie: &Tie, tied: bool) {
+    let tie_type = match tie {
+        Tie::start => "start",
+        Tie::stop => "stop"
+    };
+    ties.retain(|other| other != tie);
+    for notations in xml_tags.iter_mut().filter(|tag| tag.name.local_name == "notations") {
+        notations.retain_children(|child| {
+            child.name.local_name != "tied" || child.get_attribute_value("type").as_deref() != Some(tie_type)
+        });
+    }
+    xml_tags.retain(|tag| tag.name.local_name != "notations" || !tag.child_tags.is_empty());
+    if !tied {
+        return;
+    }
+    ties.push(tie.clone());
+    // stop before start, as in the schema examples
+    ties.sort_by_key(|tie| *tie == Tie::start);
+    let mut tied_tag = XmlTag::new("tied");
+    tied_tag.add_attribute("type", tie_type);
+    match xml_tags.iter_mut().find(|tag| tag.name.local_name == "notations") {
+        Some(notations) => { notations.direct_add_tag(tied_tag); }
+        None => {
+            let mut notations = XmlTag::new("notations");
+            notations.direct_add_tag(tied_tag);
+            xml_tags.push(notations);
+        }
+    }
 }
 
-impl<'a> Gnote<'a>  {
-    fn from_xml_tag(xml_tag: &'a XmlTag, attrs: &attributes) -> Gnote<'a> {
-        Gnote {
-            _xml_attrs: &xml_tag.attributes,
+/// Children of <note> that are parsed into Gnote fields. Everything else is kept
+/// in Gnote::_xml_tags
+const MODELED_NOTE_TAGS: [&str; 11] = [
+    "chord", "pitch", "accidental", "duration",
+    "time-modification", "type", "dot", "tie", "lyric", "voice", "staff"
+];
+
+impl Gnote  {
+    pub fn from_xml_tag(xml_tag: &XmlTag, attrs: &attributes) -> Gnote {
+        Self::try_from_xml_tag(xml_tag, attrs).unwrap()
+    }
+
+    pub fn try_from_xml_tag(xml_tag: &XmlTag, attrs: &attributes) -> MashResult<Gnote> {
+        // errors from child parsers get the path of <note> prepended
+        let within_note = |err: MashError| err.within(xml_tag);
+        Ok(Gnote {
+            _xml_attrs: xml_tag.attributes.clone(),
+            _xml_tags: xml_tag.child_tags
+                .iter()
+                .filter(|tag| tag.is_element() && !MODELED_NOTE_TAGS.contains(&tag.name.local_name.as_str()))
+                .cloned()
+                .chain(xml_tag.anchored_comments())
+                .collect(),
 
             variant: {
                 if xml_tag.path_exists("rest") { GnoteVariants::Rest }
@@ -255,78 +582,320 @@ impl<'a> Gnote<'a>  {
                 else { GnoteVariants::Note }
             },
 
-            pitch: vec![
-                Pitch::from_xml_tag(xml_tag.search_path_unique("pitch").unwrap())
-            ],
+            // rests carry no <pitch>
+            pitch: xml_tag.search_path_unique("pitch")
+                .map(|pitch| Pitch::try_from_xml_tag(pitch).map_err(within_note))
+                .transpose()?
+                .into_iter()
+                .collect(),
 
             accidental: xml_tag.search_path_unique("accidental")
-                .and_then(|acc| Some(Accidental::from_xml_tag(acc))),
+                .map(|acc| Accidental::try_from_xml_tag(acc).map_err(within_note))
+                .transpose()?,
 
-            duration: Frac::new(
-                xml_tag.get_tag_content_as("duration").unwrap(),
-                attrs.divisions.unwrap() as u32
-            ),
+            // grace notes take no time and have no <duration>
+            duration: if xml_tag.path_exists("grace") {
+                Frac::from(0u32)
+            } else {
+                attrs.read_duration(xml_tag)?
+            },
 
             time_mod: xml_tag.search_path_unique("time-modification")
-                .and_then(|time_mod| Some(TimeModification::from_xml_tag(time_mod))),
+                .map(|time_mod| TimeModification::try_from_xml_tag(time_mod).map_err(within_note))
+                .transpose()?,
 
-            length_type: xml_tag
-                .search_path_unique("type").as_ref().unwrap()
-                .text.as_ref().unwrap()
-                .clone(),
+            length_type: xml_tag.try_get_tag_content("type")?,
 
             dot: xml_tag.count_tag("dot"),
 
-            tie: xml_tag.search_path_unique("tie")
-                .and_then(|x| Some(Tie::from_xml_tag(x))),
+            tie: xml_tag.search_path("tie")
+                .iter()
+                .map(|x| Tie::try_from_xml_tag(x).map_err(within_note))
+                .collect::<MashResult<Vec<Tie>>>()?,
 
             lyrics: xml_tag.search_path("lyric")
                 .iter()
-                .map(|x| -> Lyric {Lyric::from_xml_tag(x)} )
-                .collect()
+                .map(|x| Lyric::try_from_xml_tag(x).map_err(within_note))
+                .collect::<MashResult<Vec<Lyric>>>()?,
+
+            voice: xml_tag.try_get_tag_content("voice")?,
+
+            staff: xml_tag.try_get_tag_content_as("staff")?,
+
+            chord_tones: Vec::new()
+        })
+    }
+
+    /// Write self as <note>. attrs supplies the divisions used for <duration>.
+    /// Only the first tone of a merged chord is written, see to_xml_tags
+    pub fn to_xml_tag(&self, attrs: &attributes) -> MashResult<XmlTag> {
+        let head = ChordTone {
+            _xml_attrs: self._xml_attrs.clone(),
+            _xml_tags: self._xml_tags.clone(),
+            accidental: self.accidental.clone(),
+            tie: self.tie.clone()
+        };
+        // a <chord/> note not merged yet writes its <chord/> back
+        self.tone_to_xml_tag(attrs, self.pitch.first(), &head, self.is_unmerged_chord_tone())
+    }
+
+    /// Write self as <note>s: one for a note or rest, and for a merged chord the
+    /// first tone followed by a <chord/> note for each other tone, in the order of pitch
+    pub fn to_xml_tags(&self, attrs: &attributes) -> MashResult<Vec< XmlTag>> {
+        let mut tags = Vec::with_capacity(self.pitch.len());
+        tags.push(self.to_xml_tag(attrs)?);
+        for (pitch, tone) in self.pitch.iter().skip(1).zip(self.chord_tones.iter()) {
+            tags.push(self.tone_to_xml_tag(attrs, Some(pitch), tone, true)?);
         }
+        Ok(tags)
     }
 
-    fn to_xml_tag(&self) -> XmlTag {
+    /// One <note> with the duration of self and the pitch and details of a tone
+    fn tone_to_xml_tag(&self,
+                       attrs: &attributes,
+                       pitch: Option<&Pitch>,
+                       tone: &ChordTone,
+                       chord: bool) -> MashResult<XmlTag> {
         let mut builder = XmlTag::new_tag_builder();
-        let mut note = builder.add_tag("note");
-        note.add_attributes(self._xml_attrs);
-        // variant
-        if self.variant != GnoteVariants::Note {
-            note.add_tag({
-                match self.variant {
-                    GnoteVariants::Rest => "rest",
-                    GnoteVariants::Chord => "chord",
-                    _ => unreachable!()
-                }
-            });
+        let note = builder.add_tag("note");
+        note.add_attributes(&tone._xml_attrs);
+        // variant. A rest read from a <note> keeps its own <rest>, with measure="yes"
+        // or a display position, among the tags we dont care about
+        if self.variant == GnoteVariants::Rest {
+            if !tone._xml_tags.iter().any(|tag| tag.name.local_name == "rest") {
+                note.add_tag("rest");
+            }
+        } else if chord {
+            note.add_tag("chord");
         }
-        // pitch
-        if self.variant == GnoteVariants::Note {
-            note.direct_add_tag(self.pitch.last().unwrap().to_xml_tag());
-        } else if self.variant == GnoteVariants::Chord {
-            todo!()
-        } else { unreachable!() }
-        // accidental
-        if self.accidental.is_some() {
-            note.direct_add_tag(self.accidental.as_ref().unwrap().to_xml_tag());
+        // pitch. An <unpitched> note keeps its <unpitched> with the tags we dont care about
+        if self.variant != GnoteVariants::Rest {
+            match pitch {
+                Some(pitch) => { note.direct_add_tag(pitch.to_xml_tag()); }
+                None if tone._xml_tags.iter().any(|tag| tag.name.local_name == "unpitched") => {}
+                None => return Err(MashError::MissingTag {
+                    location: Location { path: vec!["note".to_string(), "pitch".to_string()], position: None }
+                })
+            }
+        }
+        // duration, which grace notes leave out
+        if !self.is_grace() {
+            let count = note_attr::duration_to_divisions(&self.duration, &attrs.require_divisions_to_write()?)
+                .map_err(|err| err.within(note))?;
+            note.add_tag("duration").add_text(count);
+        }
+        // tie
+        for tie in tone.tie.iter() {
+            note.direct_add_tag(tie.to_xml_tag());
+        }
+        // voice
+        if let Some(voice) = self.voice.as_ref() {
+            note.add_tag("voice").add_text(voice);
         }
-        // duration
-        todo!();
         // length_type
-        todo!();
+        if let Some(length_type) = self.length_type.as_ref() {
+            note.add_tag("type").add_text(length_type);
+        }
         // dot
-        todo!();
-        // tie
-        todo!();
-        // lyrics
-        todo!();
-        todo!()
+        for _ in 0..self.dot {
+            note.direct_add_tag(XmlTag::new("dot"));
+        }
+        // accidental
+        if let Some(accidental) = tone.accidental.as_ref() {
+            note.direct_add_tag(accidental.to_xml_tag());
+        }
+        // time modification
+        if let Some(time_mod) = self.time_mod.as_ref() {
+            note.direct_add_tag(time_mod.to_xml_tag());
+        }
+        // staff
+        if let Some(staff) = self.staff {
+            note.add_tag("staff").add_text(staff.to_string());
+        }
+        // tags we dont care about, and comments once the note is in order
+        for tag in tone._xml_tags.iter().filter(|tag| tag.is_element()) {
+            note.direct_add_tag(tag.clone());
+        }
+        // lyrics, on the first tone only
+        if !chord || self.is_unmerged_chord_tone() {
+            for lyric in self.lyrics.iter() {
+                note.direct_add_tag(lyric.to_xml_tag());
+            }
+        }
+        let mut note = builder.built_tag();
+        note.restore_comments(tone._xml_tags.iter().filter(|tag| !tag.is_element()));
+        Ok(note)
+    }
+
+    /// A grace note, written with <grace> and without <duration>
+    pub fn is_grace(&self) -> bool {
+        self._xml_tags.iter().any(|tag| tag.name.local_name == "grace")
+    }
+
+    /// A <chord/> note read on its own, which does not move the time cursor
+    pub fn is_unmerged_chord_tone(&self) -> bool {
+        self.variant == GnoteVariants::Chord && self.chord_tones.is_empty()
+    }
+
+    /// Type, dots and time modification as written, None without a <type>
+    pub fn notation(&self) -> Option<Notation> {
+        Some(Notation {
+            length_type: self.length_type.clone()?,
+            dot: self.dot,
+            time_mod: self.time_mod.clone()
+        })
+    }
+
+    /// Derive type, dots and time modification from duration, e.g. for a note
+    /// built in code. Returns false and leaves self as it is when no single note
+    /// can show duration, see Notation::tied_from_duration
+    pub fn notate(&mut self) -> bool {
+        match Notation::from_duration(&self.duration) {
+            Some(notation) => {
+                self.length_type = Some(notation.length_type);
+                self.dot = notation.dot;
+                self.time_mod = notation.time_mod;
+                true
+            }
+            None => false
+        }
     }
 
-    /// Merge the notes that belong to a chord in given LinkedList.
-    fn merge_notes_in_chords(gnotes: &mut LinkedList<Gnote>) {
-        todo!()
+    /// Shorten self to at and return the rest of it, which starts at at. Pitched
+    /// notes are tied across the split; rests are not. Lyrics stay with self and
+    /// beams, like measure="yes" on a rest, are dropped from both, since they no
+    /// longer match. Type, dots and time modification are left as they were, see
+    /// into_notated
+    pub fn split_off(&mut self, at: &note_attr::Duration) -> Gnote {
+        assert!(*at > Frac::from(0u32) && *at < self.duration,
+                "cannot split a note of {} at {}", self.duration, at);
+        let mut rest = self.clone();
+        rest.duration = &self.duration - at;
+        self.duration = at.clone();
+        rest.lyrics.clear();
+        for gnote in [&mut *self, &mut rest].iter_mut() {
+            gnote._xml_tags.retain(|tag| tag.name.local_name != "beam");
+            for rest_tag in gnote._xml_tags.iter_mut().filter(|tag| tag.name.local_name == "rest") {
+                rest_tag.attributes.retain(|attr| attr.name.local_name != "measure");
+            }
+            for tone in gnote.chord_tones.iter_mut() {
+                tone._xml_tags.retain(|tag| tag.name.local_name != "beam");
+            }
+        }
+        // a tie into self stays on self, a tie out of self moves to rest
+        self.set_tie(Tie::start, false);
+        rest.set_tie(Tie::stop, false);
+        if self.variant != GnoteVariants::Rest {
+            self.set_tie(Tie::start, true);
+            rest.set_tie(Tie::stop, true);
+        }
+        rest
+    }
+
+    /// self as tied notes that can each be written, longest first. Just self,
+    /// with type, dots and time modification derived again, when a single note can
+    /// show its duration. self is returned unchanged if its duration cannot be
+    /// written at all, see Notation::tied_from_duration
+    pub fn into_notated(mut self) -> Vec< Gnote> {
+        if self.notate() {
+            return vec![self];
+        }
+        let notations = match Notation::tied_from_duration(&self.duration) {
+            Some(notations) => notations,
+            None => return vec![self]
+        };
+        let mut gnotes = Vec::with_capacity(notations.len());
+        let last = notations.len() - 1;
+        for (nth, notation) in notations.into_iter().enumerate() {
+            let rest = if nth < last {
+                Some(self.split_off(&notation.duration().unwrap()))
+            } else {
+                None
+            };
+            self.length_type = Some(notation.length_type);
+            self.dot = notation.dot;
+            self.time_mod = notation.time_mod;
+            gnotes.push(self);
+            match rest {
+                Some(rest) => self = rest,
+                None => break
+            }
+        }
+        gnotes
+    }
+
+    /// Ties of the first tone
+    pub fn ties(&self) -> &[Tie] {
+        &self.tie
+    }
+
+    /// Ties of the tone of pitch[nth]
+    pub fn ties_of(&self, nth: usize) -> &[Tie] {
+        match nth {
+            0 => &self.tie,
+            _ => &self.chord_tones[nth - 1].tie
+        }
+    }
+
+    /// Accidental shown on the tone of pitch[nth]
+    pub fn accidental_of(&self, nth: usize) -> Option<&Accidental> {
+        match nth {
+            0 => self.accidental.as_ref(),
+            _ => self.chord_tones[nth - 1].accidental.as_ref()
+        }
+    }
+
+    /// <notehead> of the tone of pitch[nth], e.g. "diamond"
+    pub fn notehead_of(&self, nth: usize) -> Option<&str> {
+        let xml_tags = match nth {
+            0 => &self._xml_tags,
+            _ => &self.chord_tones[nth - 1]._xml_tags
+        };
+        xml_tags.iter()
+            .find(|tag| tag.name.local_name == "notehead")
+            .and_then(|tag| tag.text.as_deref())
+    }
+
+    /// Add or remove tie on every tone, along with the <notations><tied> that draws it
+    pub fn set_tie(&mut self, tie: Tie, tied: bool) {
+        set_tie_of(&mut self.tie, &mut self._xml_tags, &tie, tied);
+        for tone in self.chord_tones.iter_mut() {
+            set_tie_of(&mut tone.tie, &mut tone._xml_tags, &tie, tied);
+        }
+    }
+
+    /// Collapse every run of a note followed by <chord/> notes into one Chord
+    /// whose pitch holds the tones in the order they were written. gnotes are the
+    /// notes at one offset in the order they were read, so a <chord/> note
+    /// follows the note it belongs to. Rests and a leading <chord/> note are kept
+    /// as they are
+    pub fn merge_notes_in_chords(gnotes: &mut Vec< Gnote>) {
+        let mut merged: Vec< Gnote> = Vec::with_capacity(gnotes.len());
+        for gnote in gnotes.drain(..) {
+            match merged.last_mut() {
+                Some(head) if gnote.is_unmerged_chord_tone() && head.variant != GnoteVariants::Rest => {
+                    head.add_chord_tone(gnote);
+                }
+                _ => merged.push(gnote)
+            }
+        }
+        *gnotes = merged;
+    }
+
+    /// Add the tones of other to self, which becomes a Chord. The duration, type
+    /// and dots of self are kept; lyrics of other stay with its tone
+    fn add_chord_tone(&mut self, other: Gnote) {
+        self.variant = GnoteVariants::Chord;
+        let mut xml_tags = other._xml_tags;
+        xml_tags.extend(other.lyrics.iter().map(|lyric| lyric.to_xml_tag()));
+        self.chord_tones.push(ChordTone {
+            _xml_attrs: other._xml_attrs,
+            _xml_tags: xml_tags,
+            accidental: other.accidental,
+            tie: other.tie
+        });
+        self.chord_tones.extend(other.chord_tones);
+        self.pitch.extend(other.pitch);
     }
 }
 
@@ -356,6 +925,197 @@ mod tests{
 
     }
 
+    #[test]
+    fn test_write_note() {
+        let attr = attributes::from_xml_tag(&XmlTag::from_buffer(attr()));
+        for note_xml in [note_xml1(), note_xml2()].iter() {
+            let note_tag = XmlTag::from_buffer(*note_xml);
+            let gnote = Gnote::from_xml_tag(&note_tag, &attr);
+            let written = gnote.to_xml_tag(&attr).unwrap();
+            assert_eq!(Gnote::from_xml_tag(&written, &attr), gnote);
+        }
+    }
+
+    #[test]
+    fn test_write_unpitched() {
+        let attr = attributes::from_xml_tag(&XmlTag::from_buffer(attr()));
+        let note_tag = XmlTag::from_buffer(r#"<note>
+          <unpitched><display-step>E</display-step><display-octave>4</display-octave></unpitched>
+          <duration>6</duration>
+          <type>quarter</type>
+        </note>"#.as_bytes());
+        let gnote = Gnote::from_xml_tag(&note_tag, &attr);
+        let written = gnote.to_xml_tag(&attr).unwrap();
+        assert!(written.path_exists("unpitched/display-step"));
+        assert_eq!(Gnote::from_xml_tag(&written, &attr), gnote);
+
+        // no divisions to write <duration> with
+        match gnote.to_xml_tag(&attributes::default()) {
+            Err(MashError::MissingTag { location }) => {
+                assert_eq!(location.path, vec!["attributes", "divisions"]);
+            }
+            other => panic!("expected MissingTag, got {:?}", other)
+        }
+        // a triplet eighth has no decimal count of 2.5 divisions
+        let mut decimal = attr.clone();
+        decimal.divisions = Some(Frac::new(5, 2));
+        let mut triplet = gnote.clone();
+        triplet.duration = Frac::new(1, 3);
+        match triplet.to_xml_tag(&decimal) {
+            Err(MashError::InvalidValue { value, location }) => {
+                assert_eq!(value, "5/6");
+                assert_eq!(location.path, vec!["note", "duration"]);
+            }
+            other => panic!("expected InvalidValue, got {:?}", other)
+        }
+        // neither <pitch> nor <unpitched>
+        let mut bare = gnote.clone();
+        bare._xml_tags.clear();
+        match bare.to_xml_tag(&attr) {
+            Err(MashError::MissingTag { location }) => {
+                assert_eq!(location.path, vec!["note", "pitch"]);
+            }
+            other => panic!("expected MissingTag, got {:?}", other)
+        }
+    }
+
+    #[test]
+    fn test_write_schema_order() {
+        let child_names = |tag: &XmlTag| -> Vec<String> {
+            tag.child_tags.iter().map(|child| child.name.local_name.clone()).collect()
+        };
+        let attr = attributes::from_xml_tag(&XmlTag::from_buffer(attr()));
+        let note_tag = XmlTag::from_buffer(note_xml1());
+        let written = Gnote::from_xml_tag(&note_tag, &attr).to_xml_tag(&attr).unwrap();
+        assert_eq!(child_names(&written),
+                   vec!["pitch", "duration", "tie", "voice", "type", "dot",
+                        "stem", "staff", "lyric", "lyric"]);
+
+        let pitch = Pitch { step: Step::B, alter: Some(-1), octave: 3 };
+        assert_eq!(child_names(&pitch.to_xml_tag()), vec!["step", "alter", "octave"]);
+        assert_eq!(child_names(&Tie::start.to_xml_tag()), Vec::<String>::new());
+        assert_eq!(Tie::start.to_xml_tag().name.local_name, "tie");
+    }
+
+    #[test]
+    fn test_notation() {
+        let notation = |duration: Frac| Notation::from_duration(&duration);
+        let half = notation(Frac::from(2u32)).unwrap();
+        assert_eq!((half.length_type.as_str(), half.dot, half.time_mod), ("half", 0, None));
+        assert_eq!(notation(Frac::new(3, 4)).unwrap().dot, 1);
+        assert_eq!(notation(Frac::new(7, 2)).unwrap().dot, 2);
+        let triplet = notation(Frac::new(1, 3)).unwrap();
+        assert_eq!(triplet.length_type, "eighth");
+        assert_eq!(triplet.time_mod, Some(TimeModification::new(3, 2)));
+        assert_eq!(notation(Frac::new(1, 5)).unwrap().time_mod, Some(TimeModification::new(5, 4)));
+        assert_eq!(notation(Frac::new(1, 3)).unwrap().duration(), Some(Frac::new(1, 3)));
+        // ties rather than odd tuplets
+        assert_eq!(notation(Frac::from(5u32)), None);
+        assert_eq!(notation(Frac::new(5, 8)), None);
+        assert_eq!(notation(Frac::from(0u32)), None);
+
+        for duration in [Frac::from(5u32), Frac::new(5, 8), Frac::new(5, 6), Frac::new(9, 4), Frac::from(12u32)].iter() {
+            let tied = Notation::tied_from_duration(duration).unwrap();
+            assert!(tied.len() > 1);
+            let total: Frac = tied.iter().map(|notation| notation.duration().unwrap()).sum();
+            assert_eq!(&total, duration);
+        }
+        let tied = Notation::tied_from_duration(&Frac::new(5, 6)).unwrap();
+        let types: Vec< &str> = tied.iter().map(|notation| notation.length_type.as_str()).collect();
+        assert_eq!(types, vec!["quarter", "16th"]);
+        assert_eq!(Notation::tied_from_duration(&Frac::new(1, 1024)), None);
+        assert_eq!(Notation::tied_from_duration(&Frac::new(1, 257)), None);
+    }
+
+    #[test]
+    fn test_notate() {
+        let attr = attributes::from_xml_tag(&XmlTag::from_buffer(attr()));
+        let note_tag = XmlTag::from_buffer(note_xml2());
+        let mut gnote = Gnote::from_xml_tag(&note_tag, &attr);
+        let written = gnote.notation();
+        gnote.length_type = None;
+        gnote.dot = 0;
+        assert!(gnote.notate());
+        assert_eq!(gnote.notation(), written);
+
+        gnote.duration = Frac::new(5, 4);
+        assert!(!gnote.notate());
+        assert_eq!(gnote.notation(), written);
+    }
+
+    #[test]
+    fn test_split_off() {
+        let attr = attributes::from_xml_tag(&XmlTag::from_buffer(attr()));
+        let mut gnote = Gnote::from_xml_tag(&XmlTag::from_buffer(note_xml1()), &attr);
+        // the half note is tied to the next one
+        let rest = gnote.split_off(&Frac::new(1, 2));
+        assert_eq!(gnote.duration, Frac::new(1, 2));
+        assert_eq!(rest.duration, Frac::new(3, 2));
+        assert_eq!(gnote.ties(), &[Tie::start]);
+        assert_eq!(rest.ties(), &[Tie::stop, Tie::start]);
+        assert_eq!((gnote.lyrics.len(), rest.lyrics.len()), (2, 0));
+        let tied: Vec< String> = rest._xml_tags.iter()
+            .filter(|tag| tag.name.local_name == "notations")
+            .flat_map(|tag| tag.child_tags.iter())
+            .filter_map(|tag| tag.get_attribute_value("type"))
+            .collect();
+        // note_xml1 has no <tied> for its own tie, only the new one is drawn
+        assert_eq!(tied, vec!["stop"]);
+
+        // the rest is a dotted quarter, 5/6 needs a triplet quarter tied to a triplet 16th
+        let notated: Vec< Gnote> = rest.into_notated();
+        assert_eq!(notated.len(), 1);
+        assert_eq!(notated[0].notation().unwrap().dot, 1);
+        let mut long = notated[0].clone();
+        long.duration = Frac::new(5, 6);
+        let notated = long.into_notated();
+        let types: Vec< String> = notated.iter().map(|gnote| gnote.notation().unwrap().length_type).collect();
+        assert_eq!(types, vec!["quarter", "16th"]);
+        assert_eq!(notated[0].ties(), &[Tie::stop, Tie::start]);
+        assert_eq!(notated[1].ties(), &[Tie::stop, Tie::start]);
+        let written = notated[1].to_xml_tag(&attr).unwrap();
+        assert_eq!(Gnote::from_xml_tag(&written, &attr), notated[1]);
+    }
+
+    #[test]
+    fn test_merge_chord() {
+        let attr = attributes::from_xml_tag(&XmlTag::from_buffer(attr()));
+        let mut gnotes: Vec< Gnote> = [note_xml1(), note_xml2(), note_xml3()].iter()
+            .map(|note_xml| Gnote::from_xml_tag(&XmlTag::from_buffer(*note_xml), &attr))
+            .collect();
+        assert!(gnotes[1].is_unmerged_chord_tone());
+        Gnote::merge_notes_in_chords(&mut gnotes);
+        assert_eq!(gnotes.len(), 1);
+        let chord = &gnotes[0];
+        assert_eq!(chord.variant, GnoteVariants::Chord);
+        let steps: Vec< Step> = chord.pitch.iter().map(|pitch| pitch.step.clone()).collect();
+        assert_eq!(steps, vec![Step::E, Step::G, Step::B]);
+        assert_eq!(chord.ties_of(0), &[Tie::start]);
+        assert!(chord.ties_of(1).is_empty());
+        assert_eq!(chord.accidental_of(2), Some(&Accidental::flat));
+        assert_eq!(chord.notehead_of(2), Some("diamond"));
+        assert_eq!(chord.notehead_of(0), None);
+
+        // written back as the first tone and two <chord/> notes, lyrics on the first
+        let written = chord.to_xml_tags(&attr).unwrap();
+        assert_eq!(written.len(), 3);
+        assert!(!written[0].path_exists("chord") && written[0].path_exists("lyric"));
+        assert!(written[1].path_exists("chord") && written[2].path_exists("chord"));
+        assert!(!written[2].path_exists("lyric"));
+        assert_eq!(written[2].get_tag_content("pitch/step"), Some("B".to_string()));
+        let mut reread: Vec< Gnote> = written.iter()
+            .map(|note_tag| Gnote::from_xml_tag(note_tag, &attr))
+            .collect();
+        Gnote::merge_notes_in_chords(&mut reread);
+        assert_eq!(&reread, &gnotes);
+
+        // a rest never takes chord tones
+        let rest = Gnote::from_xml_tag(&XmlTag::from_buffer(r#"<note><rest/><duration>6</duration></note>"#.as_bytes()), &attr);
+        let mut gnotes = vec![rest, Gnote::from_xml_tag(&XmlTag::from_buffer(note_xml2()), &attr)];
+        Gnote::merge_notes_in_chords(&mut gnotes);
+        assert_eq!(gnotes.len(), 2);
+    }
+
     #[test]
     fn test_2() {
 
@@ -400,6 +1160,23 @@ mod tests{
         </note>"#.as_bytes();
     }
 
+    fn note_xml3 () -> &'static [u8] {
+        return r#"<note default-x="116.30" default-y="-100.00">
+        <chord/>
+        <pitch>
+          <step>B</step>
+          <alter>-1</alter>
+          <octave>3</octave>
+          </pitch>
+        <duration>12</duration>
+        <voice>5</voice>
+        <type>half</type>
+        <accidental>flat</accidental>
+        <notehead>diamond</notehead>
+        <staff>2</staff>
+        </note>"#.as_bytes();
+    }
+
     fn attr () -> &'static [u8] {
         return r#"<attributes>
         <divisions>6</divisions>
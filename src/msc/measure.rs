@@ -1,27 +1,221 @@
 use std::rc::Rc;
-use crate::msc::gnote::Gnote;
+use crate::msc::gnote::{Gnote, GnoteVariants};
 use crate::parser::xml_tag::XmlTag;
 use crate::msc::attributes::attributes;
-use std::collections::{BTreeMap, HashMap};
-use crate::msc::gnote::note_attr::Offset;
+use std::collections::HashMap;
+use crate::msc::note_map::NoteMap;
+use crate::msc::gnote::note_attr::{Offset, Duration, duration_to_divisions};
+use crate::libs::frac::Frac;
+use xml::attribute::OwnedAttribute;
+use crate::libs::error::{MashError, MashResult, Location};
 
 
-#[derive(Debug)]
-pub struct Measure<'a> {
-    // Contains extra tags we dont care about
-    _xml_tags: Vec< &'a XmlTag>,
+#[derive(Debug, Clone, PartialEq)]
+pub struct Measure {
+    // attributes of <measure> such as number and width
+    pub(crate) _xml_attrs: Vec<OwnedAttribute>,
+    // Contains extra tags we dont care about, along with the offset they appear at
+    pub(crate) _xml_tags: Vec< (Offset, XmlTag)>,
 
-    pub number: u16,
-    pub notes: BTreeMap< Offset, Gnote<'a>>
+    pub number: String, // a token such as "12a" or "X1", not always a number
+    pub duration: Duration,
+    pub notes: NoteMap
 }
 
-impl<'a> Measure<'a> {
-    fn from_xml_tag(xml_tag: &'a XmlTag) -> Measure<'a> {
-        unimplemented!()
+impl Measure {
+    /// An empty measure of the given duration
+    pub fn new(number: &str, duration: Duration) -> Measure {
+        let mut measure = Measure {
+            _xml_attrs: Vec::with_capacity(2),
+            _xml_tags: Vec::with_capacity(5),
+            number: String::new(),
+            duration,
+            notes: NoteMap::new()
+        };
+        measure.set_number(number);
+        measure
     }
 
-    fn to_xml_tag(&self) -> XmlTag {
-        unimplemented!()
+    /// Change the number, written as the number attribute of <measure>
+    pub fn set_number(&mut self, number: &str) {
+        self.number = number.to_string();
+        match self._xml_attrs.iter_mut().find(|attr| attr.name.local_name == "number") {
+            Some(attr) => attr.value = number.to_string(),
+            None => self._xml_attrs.insert(0, OwnedAttribute::new("number".parse().unwrap(), number))
+        }
+    }
+
+    /// Parse <measure>. attrs holds the attributes in effect at the start of the
+    /// measure and is updated in place by any <attributes> found inside it.
+    pub fn from_xml_tag(xml_tag: &XmlTag, attrs: &mut attributes) -> Measure {
+        Self::try_from_xml_tag(xml_tag, attrs).unwrap()
+    }
+
+    pub fn try_from_xml_tag(xml_tag: &XmlTag, attrs: &mut attributes) -> MashResult<Measure> {
+        Self::try_from_content(xml_tag, xml_tag, attrs)
+    }
+
+    /// Parse one measure of a part in a score-timewise document: xml_tag is
+    /// the <measure> and part_tag the <part> inside it holding the notes
+    pub fn try_from_timewise_xml_tag(xml_tag: &XmlTag,
+                                     part_tag: &XmlTag,
+                                     attrs: &mut attributes) -> MashResult<Measure> {
+        Self::try_from_content(xml_tag, part_tag, attrs)
+            .map_err(|err| err.within(xml_tag))
+    }
+
+    /// Measure number and attributes are read from xml_tag, notes and the other
+    /// children from content_tag. They are the same <measure> in partwise documents
+    fn try_from_content(xml_tag: &XmlTag,
+                        content_tag: &XmlTag,
+                        attrs: &mut attributes) -> MashResult<Measure> {
+        if xml_tag.name.local_name != "measure" {
+            return Err(MashError::UnexpectedTag {
+                expected: "measure".to_string(), location: Location::of(xml_tag)
+            });
+        }
+        let within_measure = |err: MashError| err.within(content_tag);
+        let mut measure = Measure {
+            _xml_attrs: xml_tag.attributes.clone(),
+            _xml_tags: Vec::with_capacity(5),
+            number: xml_tag.require_attribute_value("number")?,
+            duration: Duration::from(0u32),
+            notes: NoteMap::new()
+        };
+
+        // Children are walked in document order. cursor is the current time
+        // position inside the measure; <backup> and <forward> move it explicitly
+        // and every non-chord <note> advances it by its own duration.
+        let mut cursor = Offset::from(0u32);
+        // the last note read and the <chord/> notes after it, which share its
+        // offset. They are merged into one Gnote once the run ends
+        let mut run_offset = Offset::from(0u32);
+        let mut run: Vec< Gnote> = Vec::with_capacity(4);
+        for child_tag in content_tag.child_tags.iter() {
+            match child_tag.name.local_name.as_str() {
+                "note" => {
+                    let gnote = Gnote::try_from_xml_tag(child_tag, attrs).map_err(within_measure)?;
+                    if !gnote.is_unmerged_chord_tone() {
+                        Self::push_chord_run(&mut measure.notes, &run_offset, &mut run);
+                        run_offset = cursor.clone();
+                        cursor += &gnote.duration;
+                    }
+                    run.push(gnote);
+                }
+                "backup" => {
+                    cursor -= Self::cursor_shift(child_tag, attrs).map_err(within_measure)?;
+                }
+                "forward" => {
+                    cursor += Self::cursor_shift(child_tag, attrs).map_err(within_measure)?;
+                }
+                "attributes" => {
+                    attrs.update(attributes::try_from_xml_tag(child_tag).map_err(within_measure)?);
+                    measure._xml_tags.push((cursor.clone(), child_tag.clone()));
+                }
+                // <direction> and everything else we dont model is kept with its position
+                _ => measure._xml_tags.push((cursor.clone(), child_tag.clone()))
+            }
+            if cursor > measure.duration {
+                measure.duration = cursor.clone();
+            }
+        }
+        Self::push_chord_run(&mut measure.notes, &run_offset, &mut run);
+        Ok(measure)
+    }
+
+    /// Merge a note and the <chord/> notes after it and file them at offset
+    fn push_chord_run(notes: &mut NoteMap, offset: &Offset, run: &mut Vec< Gnote>) {
+        Gnote::merge_notes_in_chords(run);
+        for gnote in run.drain(..) {
+            notes.push(offset.clone(), gnote);
+        }
+    }
+
+    /// Read <duration> of <backup>/<forward> in quarter notes
+    fn cursor_shift(xml_tag: &XmlTag, attrs: &attributes) -> MashResult<Duration> {
+        attrs.read_duration(xml_tag)
+    }
+
+    /// Write self as <measure>. attrs holds the attributes in effect at the start
+    /// of the measure and is updated by any <attributes> written inside it.
+    /// Notes are written one (staff, voice) at a time, in the order they first appear,
+    /// and <backup>/<forward> are regenerated from their offsets.
+    pub fn to_xml_tag(&self, attrs: &mut attributes) -> MashResult<XmlTag> {
+        let mut builder = XmlTag::new_tag_builder();
+        let measure = builder.add_tag("measure");
+        measure.add_attributes(&self._xml_attrs);
+
+        let offsets = self.notes.offsets();
+        let mut cursor = Offset::from(0u32);
+        let mut furthest = Offset::from(0u32);
+        let mut extra_tags = self._xml_tags.iter().peekable();
+        for (nth, voice) in self.notes.voices().enumerate() {
+            for offset in offsets.iter().copied() {
+                // tags we dont care about are written along with the first voice,
+                // ahead of any note at the same offset
+                while nth == 0 && extra_tags.peek().is_some_and(|(tag_offset, _)| tag_offset <= offset) {
+                    let (tag_offset, tag) = extra_tags.next().unwrap();
+                    Self::move_cursor(measure, &mut cursor, tag_offset, attrs)?;
+                    Self::write_extra_tag(measure, tag, attrs)?;
+                }
+                for gnote in self.notes.get(voice, offset) {
+                    // <chord/> notes sit at the offset of the note before them
+                    if !gnote.is_unmerged_chord_tone() {
+                        Self::move_cursor(measure, &mut cursor, offset, attrs)?;
+                        cursor += &gnote.duration;
+                    }
+                    for note_tag in gnote.to_xml_tags(attrs)? {
+                        measure.direct_add_tag(note_tag);
+                    }
+                }
+                if cursor > furthest {
+                    furthest = cursor.clone();
+                }
+            }
+        }
+        // tags after the last note, e.g. <barline location="right">
+        for (tag_offset, tag) in extra_tags {
+            Self::move_cursor(measure, &mut cursor, tag_offset, attrs)?;
+            Self::write_extra_tag(measure, tag, attrs)?;
+        }
+        if cursor > furthest {
+            furthest = cursor.clone();
+        }
+        // keep the measure as long as it was read, e.g. when it ends with a <forward>
+        if furthest < self.duration {
+            Self::move_cursor(measure, &mut furthest, &self.duration, attrs)?;
+        }
+        Ok(builder.built_tag())
+    }
+
+    fn write_extra_tag(measure_tag: &mut XmlTag, tag: &XmlTag, attrs: &mut attributes) -> MashResult<()> {
+        if tag.name.local_name == "attributes" {
+            attrs.update(attributes::try_from_xml_tag(tag)?);
+        }
+        measure_tag.direct_add_tag(tag.clone());
+        Ok(())
+    }
+
+    /// Write the <backup> or <forward> that moves cursor to offset
+    fn move_cursor(measure_tag: &mut XmlTag,
+                   cursor: &mut Offset,
+                   offset: &Offset,
+                   attrs: &attributes) -> MashResult<()> {
+        let (tag_name, shift) = if offset < cursor {
+            ("backup", &*cursor - offset)
+        } else if offset > cursor {
+            ("forward", offset - &*cursor)
+        } else {
+            return Ok(());
+        };
+        let mut builder = XmlTag::new_tag_builder();
+        let shift_tag = builder.add_tag(tag_name);
+        let count = duration_to_divisions(&shift, &attrs.require_divisions_to_write()?)
+            .map_err(|err| err.within(shift_tag))?;
+        shift_tag.add_tag("duration").add_text(count);
+        measure_tag.direct_add_tag(builder.built_tag());
+        *cursor = offset.clone();
+        Ok(())
     }
 }
 
@@ -34,6 +228,146 @@ mod tests {
         xml_tag.print_debug(0);
     }
 
+    #[test]
+    fn test_from_xml_tag () {
+        let xml_tag = XmlTag::from_buffer(measure_xml());
+        let mut attrs = attributes::default();
+        let measure = Measure::from_xml_tag(&xml_tag, &mut attrs);
+
+        assert_eq!(measure.number, "1");
+        assert_eq!(attrs.divisions, Some(Frac::from(6u32)));
+        assert!(measure.duration == Frac::from(4u32));
+        // <print>, <attributes> and <direction> all sit at the start of the measure
+        assert_eq!(measure._xml_tags.len(), 3);
+        assert!(measure._xml_tags.iter().all(|(offset, _)| *offset == Frac::from(0u32)));
+
+        let offsets: Vec<&Offset> = measure.notes.offsets();
+        assert_eq!(offsets, vec![&Frac::from(0u32), &Frac::from(1u32),
+                                 &Frac::new(5, 2), &Frac::from(3u32)]);
+        // first note of voice 1 plus the whole-note chord of voice 5 after <backup>,
+        // its three tones merged into one Gnote
+        let first_beat = measure.notes.at(&Frac::from(0u32));
+        assert_eq!(first_beat.len(), 2);
+        assert_eq!(first_beat[1].variant, GnoteVariants::Chord);
+        assert_eq!(first_beat[1].pitch.len(), 3);
+        assert!(first_beat[1].duration == Frac::from(4u32));
+    }
+
+    #[test]
+    fn test_grace_note () {
+        let xml_tag = XmlTag::from_buffer(r#"<measure number="12a" implicit="yes">
+      <attributes>
+        <divisions>2</divisions>
+        </attributes>
+      <note>
+        <grace slash="yes"/>
+        <pitch><step>D</step><octave>5</octave></pitch>
+        <voice>1</voice>
+        <type>eighth</type>
+        </note>
+      <note>
+        <pitch><step>C</step><octave>5</octave></pitch>
+        <duration>2</duration>
+        <voice>1</voice>
+        <type>quarter</type>
+        </note>
+      </measure>"#.as_bytes());
+        let mut attrs = attributes::default();
+        let measure = Measure::try_from_xml_tag(&xml_tag, &mut attrs).unwrap();
+        assert_eq!(measure.number, "12a");
+        assert_eq!(measure.duration, Frac::from(1u32));
+        // the grace note takes no time, it comes right before the note it leads to
+        let downbeat = measure.notes.at(&Frac::from(0u32));
+        assert_eq!(downbeat.len(), 2);
+        assert!(downbeat[0].is_grace());
+        assert_eq!(downbeat[0].duration, Frac::from(0u32));
+        assert!(!downbeat[1].is_grace());
+
+        let written = measure.to_xml_tag(&mut attributes::default()).unwrap();
+        let grace = written.search_path("note").front().cloned().unwrap();
+        assert!(grace.path_exists("grace"));
+        assert!(!grace.path_exists("duration"));
+        assert_eq!(written.count_tag("backup") + written.count_tag("forward"), 0);
+        assert_eq!(Measure::from_xml_tag(&written, &mut attributes::default()), measure);
+    }
+
+    #[test]
+    fn test_try_from_xml_tag_error () {
+        let xml_tag = XmlTag::from_buffer(r#"<measure number="3">
+      <attributes>
+        <divisions>2</divisions>
+        </attributes>
+      <note>
+        <pitch>
+          <step>C</step>
+          <octave>4</octave>
+          </pitch>
+        <duration>2</duration>
+        <accidental>quarter-sharp</accidental>
+        </note>
+      </measure>"#.as_bytes());
+        // accidentals beyond the common ones are kept as written
+        let mut attrs = attributes::default();
+        let measure = Measure::try_from_xml_tag(&xml_tag, &mut attrs).unwrap();
+        let written = measure.to_xml_tag(&mut attrs).unwrap();
+        assert_eq!(written.try_get_tag_content("note/accidental").unwrap().as_deref(), Some("quarter-sharp"));
+        assert_eq!(Measure::try_from_xml_tag(&written, &mut attrs).unwrap(), measure);
+
+        let xml_tag = XmlTag::from_buffer(r#"<measure number="3">
+      <attributes>
+        <divisions>2</divisions>
+        </attributes>
+      <note>
+        <pitch>
+          <step>H</step>
+          <octave>4</octave>
+          </pitch>
+        <duration>2</duration>
+        </note>
+      </measure>"#.as_bytes());
+        let err = Measure::try_from_xml_tag(&xml_tag, &mut attributes::default()).unwrap_err();
+        match &err {
+            MashError::InvalidValue { value, location } => {
+                assert_eq!(value, "H");
+                assert_eq!(location.path, vec!["measure", "note", "pitch", "step"]);
+                assert_eq!(location.position.unwrap().row, 6);
+            }
+            _ => panic!("unexpected error {:?}", err)
+        }
+
+        // a <note> before any <divisions>
+        let xml_tag = XmlTag::from_buffer(r#"<measure number="1">
+        <note><rest/><duration>4</duration></note>
+        </measure>"#.as_bytes());
+        match Measure::try_from_xml_tag(&xml_tag, &mut attributes::default()) {
+            Err(MashError::MissingTag { location }) =>
+                assert_eq!(location.path, vec!["measure", "attributes", "divisions"]),
+            other => panic!("unexpected result {:?}", other)
+        }
+    }
+
+    #[test]
+    fn test_measure_reader () {
+        use crate::parser::measure_reader::MeasureReader;
+        use crate::msc::part::Part;
+        // one measure in memory at a time, attributes carried per part
+        let mut attrs: HashMap<String, attributes> = HashMap::new();
+        let mut durations: HashMap<String, Duration> = HashMap::new();
+        for chunk in MeasureReader::from_file("src/parser/test/example6.musicxml").unwrap() {
+            let chunk = chunk.unwrap();
+            let part_attrs = attrs.entry(chunk.part_id.clone()).or_default();
+            let measure = Measure::try_from_xml_tag(&chunk.measure, part_attrs).unwrap();
+            let duration = durations.entry(chunk.part_id).or_insert(Duration::from(0u32));
+            *duration += measure.duration;
+        }
+
+        let xml_tree = XmlTag::from_file("src/parser/test/example6.musicxml");
+        for part_tag in xml_tree.search_path("part") {
+            let part = Part::from_xml_tag(part_tag);
+            assert!(durations[&part.id] == part.duration);
+        }
+    }
+
     fn measure_xml () -> &'static [u8]{
         return r#"<measure number="1" width="247.20">
       <print>
@@ -9,5 +9,6 @@ mod interval;
 mod measure;
 mod stream;
 mod attributes;
+mod note_map;
 
 mod tests;
@@ -0,0 +1,244 @@
+//! Notes of a measure or a part, kept voice by voice.
+//! Every note is filed under the (staff, voice) it is written in and then under its
+//! offset, so notes of different voices starting together never collide and one
+//! voice or staff can be walked on its own, e.g. the right hand of a piano part.
+use std::collections::BTreeMap;
+use crate::msc::gnote::Gnote;
+use crate::msc::gnote::note_attr::Offset;
+
+/// (staff, voice) of a note. Notes without <staff> are on staff 1 and notes without
+/// <voice> in voice "1", the defaults MusicXML gives them
+pub type VoiceKey = (u8, String);
+
+#[derive(Debug, Clone, Default)]
+pub struct NoteMap {
+    // voices in the order their first note was added, which is the order they
+    // are written back in. Offsets hold every note of the voice starting there
+    voices: Vec< (VoiceKey, BTreeMap< Offset, Vec< Gnote>>)>,
+}
+
+impl NoteMap {
+    pub fn new() -> Self {
+        NoteMap { voices: Vec::with_capacity(4) }
+    }
+
+    pub fn key_of(gnote: &Gnote) -> VoiceKey {
+        (gnote.staff.unwrap_or(1), gnote.voice.clone().unwrap_or_else(|| "1".to_string()))
+    }
+
+    /// Add gnote at offset, after the notes of its voice already there
+    pub fn push(&mut self, offset: Offset, gnote: Gnote) {
+        let key = Self::key_of(&gnote);
+        let nth = match self.voices.iter().position(|(voice_key, _)| *voice_key == key) {
+            Some(nth) => nth,
+            None => {
+                self.voices.push((key, BTreeMap::new()));
+                self.voices.len() - 1
+            }
+        };
+        self.voices[nth].1.entry(offset).or_default().push(gnote);
+    }
+
+    /// Number of notes, a merged chord counting once
+    pub fn len(&self) -> usize {
+        self.voices.iter()
+            .map(|(_, notes)| notes.values().map(|gnotes| gnotes.len()).sum::<usize>())
+            .sum()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Every (staff, voice) holding notes, in the order they first appeared
+    pub fn voices(&self) -> impl Iterator<Item = &VoiceKey> {
+        self.voices.iter().map(|(key, _)| key)
+    }
+
+    /// Every staff holding notes, from the top
+    pub fn staves(&self) -> Vec< u8> {
+        let mut staves: Vec< u8> = self.voices().map(|(staff, _)| *staff).collect();
+        staves.sort_unstable();
+        staves.dedup();
+        staves
+    }
+
+    /// Notes of one voice by offset
+    pub fn iter_voice<'a>(&'a self, key: &VoiceKey) -> impl Iterator<Item = (&'a Offset, &'a Gnote)> {
+        self.voices.iter()
+            .filter(|(voice_key, _)| voice_key == key)
+            .flat_map(|(_, notes)| Self::flatten(notes))
+            .collect::<Vec<_>>()
+            .into_iter()
+    }
+
+    /// Notes of every voice on staff by offset. Notes starting together keep the
+    /// order of their voices
+    pub fn iter_staff(&self, staff: u8) -> impl Iterator<Item = (&Offset, &Gnote)> {
+        self.collect_sorted(|(voice_staff, _)| *voice_staff == staff)
+    }
+
+    /// Every note by offset. Notes starting together keep the order of their voices
+    pub fn iter(&self) -> impl Iterator<Item = (&Offset, &Gnote)> {
+        self.collect_sorted(|_| true)
+    }
+
+    /// Every note starting at offset
+    pub fn at(&self, offset: &Offset) -> Vec< &Gnote> {
+        self.voices.iter()
+            .filter_map(|(_, notes)| notes.get(offset))
+            .flatten()
+            .collect()
+    }
+
+    /// Notes of one voice starting at offset
+    pub fn get(&self, key: &VoiceKey, offset: &Offset) -> &[Gnote] {
+        self.voices.iter()
+            .find(|(voice_key, _)| voice_key == key)
+            .and_then(|(_, notes)| notes.get(offset))
+            .map_or(&[], |gnotes| gnotes.as_slice())
+    }
+
+    /// Offsets where some note starts, in order
+    pub fn offsets(&self) -> Vec< &Offset> {
+        let mut offsets: Vec< &Offset> = self.voices.iter()
+            .flat_map(|(_, notes)| notes.keys())
+            .collect();
+        offsets.sort();
+        offsets.dedup();
+        offsets
+    }
+
+    /// Where the last note to finish ends, None without notes
+    pub fn end(&self) -> Option<Offset> {
+        self.iter().map(|(offset, gnote)| offset + &gnote.duration).max()
+    }
+
+    /// Notes starting in start..end, by offset, with offsets counted from start
+    pub fn range(&self, start: &Offset, end: &Offset) -> NoteMap {
+        let mut range = NoteMap::new();
+        for (_, notes) in self.voices.iter() {
+            for (offset, gnotes) in notes.range(start.clone()..end.clone()) {
+                for gnote in gnotes.iter() {
+                    range.push(offset - start, gnote.clone());
+                }
+            }
+        }
+        range
+    }
+
+    /// Every note of every voice, for edits that keep offsets and voices as they are
+    pub fn gnotes_mut(&mut self) -> impl Iterator<Item = &mut Gnote> {
+        self.voices.iter_mut()
+            .flat_map(|(_, notes)| notes.values_mut())
+            .flatten()
+    }
+
+    fn flatten(notes: &BTreeMap< Offset, Vec< Gnote>>) -> impl Iterator<Item = (&Offset, &Gnote)> {
+        notes.iter().flat_map(|(offset, gnotes)| gnotes.iter().map(move |gnote| (offset, gnote)))
+    }
+
+    fn collect_sorted(&self, keep: impl Fn(&VoiceKey) -> bool) -> std::vec::IntoIter<(&Offset, &Gnote)> {
+        let mut notes: Vec< (&Offset, &Gnote)> = self.voices.iter()
+            .filter(|(key, _)| keep(key))
+            .flat_map(|(_, notes)| Self::flatten(notes))
+            .collect();
+        // stable, so voices keep their order at equal offsets
+        notes.sort_by(|a, b| a.0.cmp(b.0));
+        notes.into_iter()
+    }
+}
+
+/// Notes by offset, as iter() gives them
+impl IntoIterator for NoteMap {
+    type Item = (Offset, Gnote);
+    type IntoIter = std::vec::IntoIter<(Offset, Gnote)>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        let mut notes: Vec< (Offset, Gnote)> = self.voices.into_iter()
+            .flat_map(|(_, notes)| notes.into_iter())
+            .flat_map(|(offset, gnotes)| gnotes.into_iter().map(move |gnote| (offset.clone(), gnote)))
+            .collect();
+        notes.sort_by(|a, b| a.0.cmp(&b.0));
+        notes.into_iter()
+    }
+}
+
+/// Same notes in the same voices. The order voices first appeared in does not matter
+impl PartialEq for NoteMap {
+    fn eq(&self, other: &Self) -> bool {
+        self.voices.len() == other.voices.len()
+            && self.voices.iter().all(|(key, notes)| {
+                other.voices.iter().any(|(other_key, other_notes)| other_key == key && other_notes == notes)
+            })
+    }
+}
+
+mod tests {
+    use super::*;
+    use crate::msc::measure::Measure;
+    use crate::msc::attributes::attributes;
+    use crate::msc::gnote::GnoteVariants;
+    use crate::parser::xml_tag::XmlTag;
+    use crate::libs::frac::Frac;
+
+    /// Two bars of a piano part as one 8/4 measure: quarters in the right hand,
+    /// a whole-note chord and two half notes in the left
+    fn piano_notes() -> NoteMap {
+        let note = |step: &str, duration: u8, voice: u8, staff: u8, chord: bool| format!(
+            "<note>{}<pitch><step>{}</step><octave>4</octave></pitch><duration>{}</duration>\
+             <voice>{}</voice><staff>{}</staff></note>",
+            if chord { "<chord/>" } else { "" }, step, duration, voice, staff
+        );
+        let mut xml = String::from("<measure number=\"1\"><attributes><divisions>1</divisions></attributes>");
+        for step in ["C", "D", "E", "F", "G", "A", "B", "C"].iter() {
+            xml += &note(step, 1, 1, 1, false);
+        }
+        xml += "<backup><duration>8</duration></backup>";
+        xml += &note("C", 4, 5, 2, false);
+        xml += &note("E", 4, 5, 2, true);
+        xml += &note("G", 2, 5, 2, false);
+        xml += &note("F", 2, 5, 2, false);
+        xml += "</measure>";
+        let mut attrs = attributes::default();
+        Measure::from_xml_tag(&XmlTag::from_buffer(xml.as_bytes()), &mut attrs).notes
+    }
+
+    #[test]
+    fn test_voices() {
+        let notes = piano_notes();
+        let voices: Vec< &VoiceKey> = notes.voices().collect();
+        assert_eq!(voices, vec![&(1, "1".to_string()), &(2, "5".to_string())]);
+        assert_eq!(notes.staves(), vec![1, 2]);
+        // both hands start on the downbeat
+        assert_eq!(notes.at(&Frac::from(0u32)).len(), 2);
+
+        let left_hand: Vec< (&Offset, &Gnote)> = notes.iter_staff(2).collect();
+        assert_eq!(left_hand.len(), 3);
+        assert_eq!(left_hand[0].1.variant, GnoteVariants::Chord);
+        let offsets: Vec< &Offset> = left_hand.iter().map(|(offset, _)| *offset).collect();
+        assert_eq!(offsets, vec![&Frac::from(0u32), &Frac::from(4u32), &Frac::from(6u32)]);
+
+        let right_hand = notes.iter_voice(&(1, "1".to_string())).count();
+        assert_eq!(right_hand, 8);
+        assert_eq!(right_hand + left_hand.len(), notes.len());
+        assert_eq!(notes.iter_voice(&(1, "5".to_string())).count(), 0);
+        assert_eq!(notes.end(), Some(Frac::from(8u32)));
+
+        // every note comes back in time order
+        let all: Vec< &Offset> = notes.iter().map(|(offset, _)| offset).collect();
+        assert_eq!(all.len(), 11);
+        assert!(all.windows(2).all(|pair| pair[0] <= pair[1]));
+        assert_eq!(notes.clone().into_iter().count(), notes.len());
+    }
+
+    #[test]
+    fn test_range() {
+        let notes = piano_notes();
+        let second = notes.range(&Frac::from(4u32), &Frac::from(8u32));
+        assert_eq!(second.at(&Frac::from(0u32)).len(), 2);
+        assert_eq!(second.len(), 6);
+        assert_eq!(notes.range(&Frac::from(0u32), &Frac::from(8u32)), notes);
+        assert!(notes.range(&Frac::from(8u32), &Frac::from(9u32)).is_empty());
+    }
+}
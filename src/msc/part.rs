@@ -1,61 +1,680 @@
 use std::rc::Rc;
 use std::borrow::Cow;
 use crate::msc::measure::{Measure};
+use crate::msc::note_map::NoteMap;
 use crate::msc::gnote::{Gnote, note_attr};
-use crate::msc::gnote::note_attr::{Offset, Duration};
+use crate::msc::gnote::note_attr::{Offset, Duration, Divisions};
 use std::cell::RefCell;
 use crate::parser::xml_tag::XmlTag;
-use crate::msc::attributes::{TimeSignature, Clef, attributes};
+use crate::msc::attributes::{TimeSignature, KeySignature, Clef, attributes};
+use crate::libs::frac::{Frac, lcm};
+use std::convert::TryFrom;
 use std::collections::{BTreeMap, LinkedList};
+use crate::libs::error::{MashError, MashResult, Location};
 
-#[derive(Debug)]
-pub struct Part<'a> {
+#[derive(Debug, Clone, PartialEq)]
+pub struct Part {
     // Tags that we don't care about
-    _xml_tags: Vec< &'a XmlTag>,
+    _xml_tags: Vec< XmlTag>,
 
-    pub attrs: attributes,
+    pub id: String, // matches the id of a <score-part> in <part-list>
     pub duration: note_attr::Duration,
-    pub notes: BTreeMap< Offset, Gnote<'a>>
+    pub measures: Vec< Measure>, // the notes are kept here, see notes
+    // changes of attributes by offset, each holding only the fields set there.
+    // What <attributes> model is kept here rather than in the measures
+    pub timeline: BTreeMap< Offset, attributes>
 }
 
-impl<'a> Part<'a> {
-    fn from_xml_tag(xml_tag: &'a XmlTag) -> Part<'a> {
-        let mut part = Part {
+impl Part {
+    pub fn from_xml_tag(xml_tag: &XmlTag) -> Part {
+        Self::try_from_xml_tag(xml_tag).unwrap()
+    }
+
+    pub fn try_from_xml_tag(xml_tag: &XmlTag) -> MashResult<Part> {
+        if xml_tag.name.local_name != "part" {
+            return Err(MashError::UnexpectedTag {
+                expected: "part".to_string(), location: Location::of(xml_tag)
+            });
+        }
+        let mut part = Self::new(xml_tag.require_attribute_value("id")?);
+
+        // attributes are carried from one measure to the next, each <attributes>
+        // only overriding the fields it mentions
+        let mut attrs = attributes::default();
+        for child_tag in xml_tag.child_tags.iter() {
+            if child_tag.name.local_name != "measure" {
+                part._xml_tags.push(child_tag.clone());
+                continue;
+            }
+            let measure = Measure::try_from_xml_tag(child_tag, &mut attrs)
+                .map_err(|err| err.within(xml_tag))?;
+            part.push_measure(measure).map_err(|err| err.within(xml_tag))?;
+        }
+        Ok(part)
+    }
+
+    /// Build the part with the given id out of the <measure>s of a score-timewise
+    /// document, each holding a <part id=...> with this part's content
+    pub fn try_from_timewise_xml_tags(id: &str, measure_tags: &[&XmlTag]) -> MashResult<Part> {
+        let mut part = Self::new(id.to_string());
+        let mut attrs = attributes::default();
+        for measure_tag in measure_tags.iter() {
+            let part_tag = measure_tag.child_tags
+                .iter()
+                .find(|tag| tag.name.local_name == "part"
+                    && tag.get_attribute_value("id").as_deref() == Some(id))
+                .ok_or_else(|| MashError::MissingTag {
+                    location: Location::of_path(measure_tag, &format!("part[@id=\"{}\"]", id))
+                })?;
+            part.push_measure(Measure::try_from_timewise_xml_tag(measure_tag, part_tag, &mut attrs)?)?;
+        }
+        Ok(part)
+    }
+
+    fn new(id: String) -> Self {
+        Part {
             _xml_tags: Vec::with_capacity(5),
-            attrs: attributes {
-                divisions: None,
-                key: None,
-                time: None,
-                clef: None,
-                staves: None
-            },
+            id,
             duration: Duration::from(0u32),
-            notes: BTreeMap::new()
+            measures: Vec::with_capacity(32),
+            timeline: BTreeMap::new()
+        }
+    }
+
+    /// Every note of the part, with offsets counted from the start of the piece.
+    /// The notes live in the measures, this is a copy of them
+    pub fn notes(&self) -> NoteMap {
+        let mut notes = NoteMap::new();
+        let mut start = Offset::from(0u32);
+        for measure in self.measures.iter() {
+            // measure offsets are relative to the barline, shift them by where
+            // the measure starts
+            for (offset, gnote) in measure.notes.iter() {
+                notes.push(&start + offset, gnote.clone());
+            }
+            start += &measure.duration;
+        }
+        notes
+    }
+
+    /// Number of staves, from <staves> or the highest staff a note is written on
+    pub fn staves(&self) -> u8 {
+        let noted = self.measures.iter()
+            .flat_map(|measure| measure.notes.staves())
+            .fold(1, u8::max);
+        self.timeline.values()
+            .filter_map(|change| change.staves)
+            .fold(noted, u8::max)
+    }
+
+    /// Attributes in effect at offset, set by the changes at or before it
+    pub fn attributes_at(&self, offset: &Offset) -> attributes {
+        let mut attrs = attributes::default();
+        for (_, change) in self.timeline.range(..=offset.clone()) {
+            attrs.update(change.clone());
+        }
+        attrs
+    }
+
+    pub fn key_at(&self, offset: &Offset) -> Option<KeySignature> {
+        self.attributes_at(offset).key
+    }
+
+    pub fn time_at(&self, offset: &Offset) -> Option<TimeSignature> {
+        self.attributes_at(offset).time
+    }
+
+    /// Clef of staff at offset, staves counted from 1 at the top
+    pub fn clef_at(&self, staff: u8, offset: &Offset) -> Option<Clef> {
+        self.attributes_at(offset).clefs.remove(&staff)
+    }
+
+    pub fn divisions_at(&self, offset: &Offset) -> Option<Divisions> {
+        self.attributes_at(offset).divisions
+    }
+
+    /// Append measure to the end of the part. What its <attributes> set goes to
+    /// the timeline, the tags are only kept for the children not modeled
+    fn push_measure(&mut self, mut measure: Measure) -> MashResult<()> {
+        let mut kept = Vec::with_capacity(measure._xml_tags.len());
+        for (offset, mut tag) in measure._xml_tags.drain(..) {
+            if tag.name.local_name == "attributes" {
+                let change = attributes::try_from_xml_tag(&tag)?;
+                self.timeline.entry(&self.duration + &offset).or_default().update(change);
+                attributes::retain_unmodeled(&mut tag);
+                if !tag.child_tags.iter().any(|child| child.is_element()) {
+                    continue;
+                }
+            }
+            kept.push((offset, tag));
+        }
+        measure._xml_tags = kept;
+        self.duration += &measure.duration;
+        self.measures.push(measure);
+        Ok(())
+    }
+
+    /// Regroup the notes into measures of the given time signatures, the last one
+    /// repeating until every note fits, e.g. &[TimeSignature::new(3, 4)] to turn the part into 3/4.
+    /// Notes crossing a barline are split there and the pieces tied, see
+    /// Gnote::split_off. Tags we dont model, such as <direction>, keep their offset
+    /// in the part. <time> is written where the time signature changes and the
+    /// measures are numbered from 1. Err, with self left as it was, when times is
+    /// empty or holds a senza-misura or a time whose bars have no length
+    pub fn rebar(&mut self, times: &[TimeSignature]) -> MashResult<()> {
+        let time_location = || Location { path: vec!["attributes".to_string(), "time".to_string()], position: None };
+        if times.is_empty() {
+            return Err(MashError::MissingTag { location: time_location() });
+        }
+        // without a positive bar length the barlines would never pass the music
+        let zero = Offset::from(0u32);
+        for time in times.iter() {
+            match time.bar_duration() {
+                Some(bar) if bar > zero && bar.is_finite() => (),
+                bar => return Err(MashError::InvalidValue {
+                    value: if time.senza_misura.is_some() {
+                        "senza-misura".to_string()
+                    } else {
+                        bar.map(|bar| bar.to_string()).unwrap_or_default()
+                    },
+                    location: time_location()
+                })
+            }
+        }
+        let part_notes = self.notes();
+        // the music ends with the last note or the last barline, whichever is later
+        let end = match part_notes.end() {
+            Some(notes_end) => notes_end.max(self.duration.clone()),
+            None => self.duration.clone()
         };
-        let attrs = attributes::from_xml_tag(xml_tag
-                                                  .search_path("attributes")
-                                                  .pop_front().unwrap());
+        let mut barlines = vec![zero.clone()];
+        let mut bar_times = Vec::new();
+        while bar_times.is_empty() || *barlines.last().unwrap() < end {
+            let time = times[bar_times.len().min(times.len() - 1)].clone();
+            barlines.push(barlines.last().unwrap() + time.bar_duration().unwrap());
+            bar_times.push(time);
+        }
+
+        // notes, split where they cross a barline
+        let mut notes = NoteMap::new();
+        for (mut start, mut gnote) in part_notes {
+            let mut split = false;
+            loop {
+                let rest = match barlines.iter().find(|barline| **barline > start) {
+                    Some(barline) if &start + &gnote.duration > *barline =>
+                        Some(gnote.split_off(&(barline - &start))),
+                    // on the last barline there is only room for a grace note
+                    _ => None
+                };
+                let pieces = if split || rest.is_some() { gnote.into_notated() } else { vec![gnote] };
+                for piece in pieces {
+                    let piece_end = &start + &piece.duration;
+                    notes.push(start, piece);
+                    start = piece_end;
+                }
+                match rest {
+                    Some(rest) => { gnote = rest; split = true; }
+                    None => break
+                }
+            }
+        }
+
+        // tags we dont model, at their offset in the part. at_end marks the ones
+        // closing their measure, e.g. <barline location="right">
+        let mut tags: Vec< (Offset, bool, XmlTag)> = Vec::new();
+        let mut measure_start = zero.clone();
+        for measure in self.measures.iter_mut() {
+            for (offset, mut tag) in measure._xml_tags.drain(..) {
+                if tag.name.local_name == "attributes" {
+                    tag.retain_children(|child| !["time", "divisions"].contains(&child.name.local_name.as_str()));
+                    if !tag.child_tags.iter().any(|child| child.is_element()) {
+                        continue;
+                    }
+                }
+                let at_end = offset > zero && offset == measure.duration;
+                tags.push((&measure_start + &offset, at_end, tag));
+            }
+            measure_start += &measure.duration;
+        }
 
-        part
+        let old_measures = std::mem::take(&mut self.measures);
+        for (nth, time) in bar_times.iter().enumerate() {
+            let (start, end) = (&barlines[nth], &barlines[nth + 1]);
+            let number = (nth + 1).to_string();
+            let mut measure = Measure::new(&number, end - start);
+            // keep what the old measure in that place had, e.g. an id. Its number,
+            // width and implicit="yes" were about the old content
+            if let Some(old) = old_measures.get(nth) {
+                measure._xml_attrs = old._xml_attrs
+                    .iter()
+                    .filter(|attr| !["number", "width", "implicit"].contains(&attr.name.local_name.as_str()))
+                    .cloned()
+                    .collect();
+                measure.set_number(&number);
+            }
+            measure.notes = notes.range(start, end);
+            if nth + 1 == bar_times.len() {
+                for gnote in notes.at(end) {
+                    measure.notes.push(end - start, gnote.clone());
+                }
+            }
+            self.measures.push(measure);
+        }
+        for (offset, at_end, tag) in tags {
+            // the measure starting at or before offset, or ending at it for at_end
+            let mut nth = barlines.iter().rposition(|barline| *barline <= offset).unwrap();
+            if at_end && nth > 0 && barlines[nth] == offset {
+                nth -= 1;
+            }
+            let nth = nth.min(self.measures.len() - 1);
+            let start = &barlines[nth];
+            self.measures[nth]._xml_tags.push((&offset - start, tag));
+        }
+        for measure in self.measures.iter_mut() {
+            // stable, so tags at one offset keep their order
+            measure._xml_tags.sort_by(|a, b| a.0.cmp(&b.0));
+        }
+        // the new time signatures replace the ones in the timeline
+        for change in self.timeline.values_mut() {
+            change.time = None;
+        }
+        self.timeline.retain(|_, change| !change.is_empty());
+        for (nth, time) in bar_times.iter().enumerate() {
+            if nth == 0 || bar_times[nth - 1] != *time {
+                self.timeline.entry(barlines[nth].clone()).or_default().time = Some(time.clone());
+            }
+        }
+        self.duration = barlines.last().unwrap().clone();
+        Ok(())
     }
 
-    fn to_xml_tag(&self) -> XmlTag {
-        unimplemented!()
+    /// Merge the children of attributes_tag into the <attributes> of measure at
+    /// offset, adding one ahead of any note there if there is none
+    fn add_to_attributes(measure: &mut Measure, offset: &Offset, attributes_tag: XmlTag) {
+        let zero = Offset::from(0u32);
+        let existing = measure._xml_tags
+            .iter_mut()
+            .find(|(tag_offset, tag)| tag_offset == offset && tag.name.local_name == "attributes");
+        match existing {
+            Some((_, tag)) => {
+                for child in attributes_tag.child_tags {
+                    tag.direct_add_tag(child);
+                }
+                tag.order_by_schema();
+            }
+            None => {
+                // after <print> and a left <barline>, as the schema has it,
+                // ahead of anything else at offset
+                let position = measure._xml_tags
+                    .iter()
+                    .position(|(tag_offset, tag)| tag_offset > offset
+                        || tag_offset == offset
+                            && (*offset != zero || !["print", "barline"].contains(&tag.name.local_name.as_str())))
+                    .unwrap_or(measure._xml_tags.len());
+                measure._xml_tags.insert(position, (offset.clone(), attributes_tag));
+            }
+        }
+    }
+
+    /// The smallest divisions every offset and duration in the part is a whole
+    /// number of, whatever divisions it was read with
+    pub fn common_divisions(&self) -> Divisions {
+        let mut divisions = 1;
+        for (offset, gnote) in self.notes().iter() {
+            divisions = lcm(divisions, offset.denom());
+            divisions = lcm(divisions, gnote.duration.denom());
+        }
+        let mut start = Offset::from(0u32);
+        for measure in self.measures.iter() {
+            for (offset, _) in measure._xml_tags.iter() {
+                divisions = lcm(divisions, (&start + offset).denom());
+            }
+            start += &measure.duration;
+            divisions = lcm(divisions, start.denom());
+        }
+        for offset in self.timeline.keys() {
+            divisions = lcm(divisions, offset.denom());
+        }
+        Divisions::from(divisions)
+    }
+
+    /// Write self as <part>. <attributes> are written from the timeline, where
+    /// something changes: restating a value already in effect is left out.
+    /// <divisions> is written once, set to common_divisions
+    pub fn to_xml_tag(&self) -> MashResult<XmlTag> {
+        let mut builder = XmlTag::new_tag_builder();
+        let part = builder.add_tag("part");
+        part.add_attribute("id", &self.id);
+
+        let mut timeline = self.timeline.clone();
+        for change in timeline.values_mut() {
+            change.divisions = None;
+        }
+        timeline.entry(Offset::from(0u32)).or_default().divisions = Some(self.common_divisions());
+
+        let mut attrs = attributes::default();
+        let mut current = attributes::default();
+        let mut changes = timeline.iter().peekable();
+        let mut start = Offset::from(0u32);
+        for (nth, measure) in self.measures.iter().enumerate() {
+            let end = &start + &measure.duration;
+            let is_last = nth + 1 == self.measures.len();
+            let mut measure = measure.clone();
+            // changes past the end of the part close the last measure
+            while let Some((offset, change)) = changes.next_if(|(offset, _)| is_last || **offset < end) {
+                let changed = change.changed_from(&current);
+                current.update(change.clone());
+                if !changed.is_empty() {
+                    let at = (offset - &start).min(measure.duration.clone());
+                    Self::add_to_attributes(&mut measure, &at, changed.to_xml_tag()?);
+                }
+            }
+            part.direct_add_tag(measure.to_xml_tag(&mut attrs)?);
+            start = end;
+        }
+        for tag in self._xml_tags.iter() {
+            part.direct_add_tag(tag.clone());
+        }
+        Ok(builder.built_tag())
     }
 }
 
 mod tests {
     use super::*;
     use std::borrow::Borrow;
-    use crate::msc::gnote::Gnote;
+    use crate::msc::gnote::{Gnote, GnoteVariants};
     use crate::msc::gnote::note_attr::*;
+    use crate::libs::frac::Frac;
+    use crate::msc::attributes::ClefSign;
 
     #[test]
     fn test_1 () {
         let mea_tag = XmlTag::from_buffer(measure_xml());
         //mea_tag.print_debug(0);
         let part = Part::from_xml_tag(&mea_tag);
-        println!("{:#?}", part.attrs);
+        println!("{:#?}", part.timeline);
+    }
+
+    #[test]
+    fn test_timeline () {
+        let part_tag = XmlTag::from_buffer(measure_xml());
+        let part = Part::from_xml_tag(&part_tag);
+        let notes = part.notes();
+
+        assert_eq!(part.id, "P1");
+        assert_eq!(part.measures.len(), 2);
+        assert_eq!(part.divisions_at(&Frac::from(0u32)), Some(Frac::from(6u32)));
+        assert!(part.duration == Frac::from(8u32));
+
+        // the tie across the barline: start on beat 4 of measure 1, stop on the downbeat of measure 2
+        let tie_start = notes.at(&Frac::from(3u32))[0];
+        let tie_stop = notes.at(&Frac::from(4u32))[0];
+        assert_eq!(tie_start.pitch, tie_stop.pitch);
+        assert!(tie_start.duration == Frac::from(1u32));
+        assert!(tie_stop.duration == Frac::new(1, 2));
+
+        // the triplet in measure 2 starts on beat 3, each note a third of a beat
+        for (nth, variant) in [GnoteVariants::Note, GnoteVariants::Rest, GnoteVariants::Note]
+            .iter().enumerate()
+        {
+            let gnote = notes.at(&(Frac::from(6u32) + Frac::new(nth as i64, 3)))[0];
+            assert_eq!(&gnote.variant, variant);
+            assert!(gnote.duration == Frac::new(1, 3));
+        }
+        assert!(!notes.at(&Frac::new(15, 2)).is_empty());
+
+        // two half-note chords of voice 5 in measure 2, next to a note of voice 1
+        assert_eq!(notes.at(&Frac::from(4u32)).len(), 2);
+        assert_eq!(notes.at(&Frac::from(6u32)).len(), 2);
+        assert_eq!(notes.at(&Frac::from(6u32))[1].pitch.len(), 3);
+        // voice 1 on the upper staff, voice 5 on the lower one
+        assert_eq!(notes.staves(), vec![1, 2]);
+        assert_eq!(notes.iter_staff(2).count(), 3);
+        // each staff has its own clef
+        assert_eq!(part.staves(), 2);
+        assert_eq!(part.clef_at(1, &Frac::from(0u32)), Some(Clef::new(ClefSign::G, 2)));
+        assert_eq!(part.clef_at(2, &Frac::from(0u32)), Some(Clef::new(ClefSign::F, 4)));
+        assert_eq!(part.clef_at(3, &Frac::from(0u32)), None);
+
+        // the notes are those of the measures, so an edit there is what notes() and
+        // the writer see
+        let mut edited = part.clone();
+        edited.measures[1].notes = NoteMap::new();
+        assert_eq!(edited.notes().len(), part.measures[0].notes.len());
+        let written = edited.to_xml_tag().unwrap();
+        assert!(written.search_path("measure").back().unwrap().search_path("note").is_empty());
+    }
+
+    #[test]
+    fn test_attribute_timeline () {
+        let part_tag = XmlTag::from_buffer(r#"<part id="P1">
+    <measure number="1">
+      <attributes>
+        <divisions>1</divisions>
+        <key><fifths>0</fifths></key>
+        <time><beats>2</beats><beat-type>4</beat-type></time>
+        <staves>2</staves>
+        <clef number="1"><sign>G</sign><line>2</line></clef>
+        <clef number="2"><sign>F</sign><line>4</line></clef>
+        <transpose><diatonic>-1</diatonic><chromatic>-2</chromatic></transpose>
+        </attributes>
+      <note><pitch><step>C</step><octave>4</octave></pitch><duration>1</duration><staff>1</staff></note>
+      <attributes>
+        <clef number="2"><sign>G</sign><line>2</line></clef>
+        </attributes>
+      <note><pitch><step>D</step><octave>4</octave></pitch><duration>1</duration><staff>1</staff></note>
+      </measure>
+    <measure number="2">
+      <attributes>
+        <divisions>2</divisions>
+        <key><fifths>2</fifths></key>
+        <clef number="1"><sign>G</sign><line>2</line></clef>
+        </attributes>
+      <note><pitch><step>E</step><octave>4</octave></pitch><duration>1</duration><staff>1</staff></note>
+      <note><pitch><step>F</step><octave>4</octave></pitch><duration>3</duration><staff>1</staff></note>
+      </measure>
+    </part>"#.as_bytes());
+        let part = Part::from_xml_tag(&part_tag);
+        let notes = part.notes();
+        let at = |quarters: u32| Frac::from(quarters);
+        // the clef change in the middle of measure 1 and what measure 2 changes
+        assert_eq!(part.timeline.keys().collect::<Vec<_>>(), vec![&at(0), &at(1), &at(2)]);
+        assert_eq!(part.clef_at(2, &Frac::new(1, 2)).unwrap().sign, ClefSign::F);
+        assert_eq!(part.clef_at(2, &at(1)).unwrap().sign, ClefSign::G);
+        assert_eq!(part.clef_at(1, &at(3)).unwrap().sign, ClefSign::G);
+        assert_eq!(part.key_at(&at(1)), Some(KeySignature::new(0, None)));
+        assert_eq!(part.key_at(&at(2)), Some(KeySignature::new(2, None)));
+        assert_eq!(part.time_at(&at(3)), Some(TimeSignature::new(2, 4)));
+        assert_eq!(part.divisions_at(&at(1)), Some(Frac::from(1u32)));
+        assert_eq!(part.divisions_at(&at(2)), Some(Frac::from(2u32)));
+        // the notes of measure 2 are read with its own divisions
+        assert_eq!(notes.at(&at(2))[0].duration, Frac::new(1, 2));
+        assert_eq!(notes.at(&Frac::new(5, 2))[0].duration, Frac::new(3, 2));
+        // <transpose> is not modeled and stays in the measure
+        assert_eq!(part.measures[0]._xml_tags.len(), 1);
+
+        let written = part.to_xml_tag().unwrap();
+        let measures = written.search_path("measure");
+        let first = measures.front().unwrap();
+        let names: Vec< &str> = first.child_tags.iter().map(|tag| tag.name.local_name.as_str()).collect();
+        assert_eq!(names, vec!["attributes", "note", "attributes", "note"]);
+        assert!(first.path_exists("attributes/transpose"));
+        // one <divisions> for the whole part, fine enough for the eighth of measure 2
+        assert_eq!(first.get_tag_content("attributes/divisions"), Some("2".to_string()));
+        // the treble clef of staff 1 is restated in measure 2 and left out
+        let second = measures.back().unwrap().search_path_unique("attributes").unwrap();
+        assert_eq!(second.count_tag("clef"), 0);
+        assert_eq!(second.count_tag("divisions"), 0);
+        assert_eq!(second.get_tag_content("key/fifths"), Some("2".to_string()));
+
+        let reread = Part::from_xml_tag(&written);
+        assert_eq!(reread.notes(), notes);
+        assert_eq!(reread.measures, part.measures);
+        for offset in [at(0), at(1), at(2)].iter() {
+            let mut expected = part.attributes_at(offset);
+            expected.divisions = Some(Frac::from(2u32));
+            assert_eq!(reread.attributes_at(offset), expected);
+        }
+    }
+
+    #[test]
+    fn test_large_divisions () {
+        // a quarter, a triplet eighth and a dotted sixteenth in 10080 divisions
+        let part_tag = XmlTag::from_buffer(r#"<part id="P1">
+    <measure number="1">
+      <attributes><divisions>10080</divisions></attributes>
+      <note><pitch><step>C</step><octave>4</octave></pitch><duration>10080</duration></note>
+      <note><pitch><step>D</step><octave>4</octave></pitch><duration>3360</duration></note>
+      <note><pitch><step>E</step><octave>4</octave></pitch><duration>3780</duration></note>
+      </measure>
+    </part>"#.as_bytes());
+        let part = Part::from_xml_tag(&part_tag);
+        let notes = part.notes();
+        assert_eq!(part.divisions_at(&Frac::from(0u32)), Some(Frac::from(10080u32)));
+        assert_eq!(notes.at(&Frac::from(1u32))[0].duration, Frac::new(1, 3));
+        assert_eq!(notes.at(&Frac::new(4, 3))[0].duration, Frac::new(3, 8));
+        assert_eq!(part.duration, Frac::new(41, 24));
+
+        // written with the smallest divisions every offset and duration is a whole number of
+        assert_eq!(part.common_divisions(), Frac::from(24u32));
+        let written = part.to_xml_tag().unwrap();
+        assert_eq!(written.get_tag_content("measure/attributes/divisions"), Some("24".to_string()));
+        let durations: Vec< String> = written.search_path("measure/note")
+            .iter()
+            .filter_map(|note| note.get_tag_content("duration"))
+            .collect();
+        assert_eq!(durations, vec!["24", "8", "9"]);
+        assert_eq!(Part::from_xml_tag(&written).notes(), part.notes());
+
+        let zero = XmlTag::from_buffer("<attributes><divisions>0</divisions></attributes>".as_bytes());
+        assert!(matches!(attributes::try_from_xml_tag(&zero), Err(MashError::InvalidValue { .. })));
+    }
+
+    #[test]
+    fn test_decimal_divisions () {
+        // divisions and durations are xs:decimal: a quarter and two eighths in 2.5 divisions
+        let part_tag = XmlTag::from_buffer(r#"<part id="P1">
+    <measure number="1">
+      <attributes><divisions>2.5</divisions></attributes>
+      <note><pitch><step>C</step><octave>4</octave></pitch><duration>2.5</duration></note>
+      <note><pitch><step>D</step><octave>4</octave></pitch><duration>1.25</duration></note>
+      <backup><duration>1.25</duration></backup>
+      <note><pitch><step>E</step><octave>4</octave></pitch><duration>1.25</duration></note>
+      </measure>
+    </part>"#.as_bytes());
+        let part = Part::from_xml_tag(&part_tag);
+        assert_eq!(part.divisions_at(&Frac::from(0u32)), Some(Frac::new(5, 2)));
+        assert_eq!(part.notes().at(&Frac::from(1u32)).len(), 2);
+        assert_eq!(part.duration, Frac::new(3, 2));
+
+        let written = part.to_xml_tag().unwrap();
+        assert_eq!(written.get_tag_content("measure/attributes/divisions"), Some("2".to_string()));
+        assert_eq!(Part::from_xml_tag(&written).notes(), part.notes());
+
+        let negative = XmlTag::from_buffer("<attributes><divisions>-2.5</divisions></attributes>".as_bytes());
+        assert!(matches!(attributes::try_from_xml_tag(&negative), Err(MashError::InvalidValue { .. })));
+        let fraction = XmlTag::from_buffer("<attributes><divisions>5/2</divisions></attributes>".as_bytes());
+        assert!(matches!(attributes::try_from_xml_tag(&fraction), Err(MashError::InvalidValue { .. })));
+    }
+
+    #[test]
+    fn test_rebar () {
+        let mut part = Part::from_xml_tag(&XmlTag::from_buffer(measure_xml()));
+        let before: Vec< Frac> = part.notes().offsets().into_iter().cloned().collect();
+        part.rebar(&[TimeSignature::new(3, 4)]).unwrap();
+        let notes = part.notes();
+        assert_eq!(part.measures.len(), 3);
+        assert_eq!(part.duration, Frac::from(9u32));
+        assert_eq!(part.time_at(&Frac::from(0u32)), Some(TimeSignature::new(3, 4)));
+
+        // the whole-note chord of beat 1 is cut at beat 4: a dotted half tied to a quarter
+        let downbeat = notes.at(&Frac::from(0u32));
+        let cut: Vec< &Gnote> = downbeat.into_iter().filter(|gnote| gnote.duration == Frac::from(3u32)).collect();
+        assert_eq!(cut.len(), 1);
+        assert_eq!(cut[0].notation().unwrap().length_type, "half");
+        assert!((0..3).all(|nth| cut[0].ties_of(nth) == [Tie::start]));
+        let tail: Vec< &Gnote> = notes.at(&Frac::from(3u32)).into_iter()
+            .filter(|gnote| gnote.ties().contains(&Tie::stop))
+            .collect();
+        assert_eq!(tail.len(), 1);
+        assert_eq!(tail[0].variant, GnoteVariants::Chord);
+        assert!((0..3).all(|nth| tail[0].ties_of(nth) == [Tie::stop]));
+        assert_eq!(tail[0].notation().unwrap().length_type, "quarter");
+        // the G tied across the old barline now sits inside measure 2
+        assert!(notes.offsets().into_iter().all(|offset| before.contains(offset)));
+        assert!(!part.measures[1].notes.at(&Frac::from(0u32)).is_empty());
+
+        // what is written reads back as the same notes, in 3/4
+        let written = part.to_xml_tag().unwrap();
+        assert_eq!(written.search_path("measure").len(), 3);
+        assert_eq!(written.get_tag_content("measure/attributes/time/beats"), Some("3".to_string()));
+        assert_eq!(written.get_tag_content("measure/attributes/divisions"), Some("6".to_string()));
+        let reread = Part::from_xml_tag(&written);
+        assert_eq!(reread.notes(), notes);
+        assert_eq!(reread.duration, Frac::from(9u32));
+        // <print> and <direction> stay in the first measure
+        assert_eq!(reread.measures[0]._xml_tags.len(), 2);
+        assert_eq!(reread.timeline, part.timeline);
+    }
+
+    #[test]
+    fn test_rebar_meter_change () {
+        let mut part = Part::from_xml_tag(&XmlTag::from_buffer(measure_xml()));
+        part.rebar(&[TimeSignature::new(2, 4), TimeSignature::new(3, 8)]).unwrap();
+        let lengths: Vec< Frac> = part.measures.iter().map(|measure| measure.duration.clone()).collect();
+        assert_eq!(lengths[..3], [Frac::from(2u32), Frac::new(3, 2), Frac::new(3, 2)]);
+        assert_eq!(part.duration, lengths.iter().sum());
+        // every piece of a split note adds back up to the note
+        let total = |part: &Part| -> Frac {
+            part.notes().iter()
+                .map(|(_, gnote)| gnote.clone())
+                .filter(|gnote| gnote.variant == GnoteVariants::Note)
+                .map(|gnote| gnote.duration.clone())
+                .sum()
+        };
+        let original = Part::from_xml_tag(&XmlTag::from_buffer(measure_xml()));
+        assert_eq!(total(&part), total(&original));
+
+        let written = part.to_xml_tag().unwrap();
+        let times: Vec< String> = written.search_path("measure").iter()
+            .filter_map(|measure| measure.get_tag_content("attributes/time/beat-type"))
+            .collect();
+        assert_eq!(times, vec!["4", "8"]);
+        assert_eq!(Part::from_xml_tag(&written).notes(), part.notes());
+    }
+
+    #[test]
+    fn test_rebar_numbers () {
+        // a pickup measure numbered 0, and a grace note on the final barline
+        let mut part = Part::from_xml_tag(&XmlTag::from_buffer(r#"<part id="P1">
+    <measure number="0" implicit="yes" width="80">
+      <attributes><divisions>1</divisions><time><beats>4</beats><beat-type>4</beat-type></time></attributes>
+      <note><pitch><step>G</step><octave>4</octave></pitch><duration>2</duration></note>
+      </measure>
+    <measure number="1" width="200">
+      <note><pitch><step>C</step><octave>5</octave></pitch><duration>4</duration></note>
+      <note><grace/><pitch><step>D</step><octave>5</octave></pitch><type>eighth</type></note>
+      </measure>
+    </part>"#.as_bytes()));
+        part.rebar(&[TimeSignature::new(3, 4)]).unwrap();
+        let numbers: Vec< &str> = part.measures.iter().map(|measure| measure.number.as_str()).collect();
+        assert_eq!(numbers, vec!["1", "2"]);
+        assert_eq!(part.duration, Frac::from(6u32));
+        let written = part.to_xml_tag().unwrap();
+        let measures = written.search_path("measure");
+        assert!(measures.iter().all(|measure| measure.attributes.len() == 1));
+        assert_eq!(measures.back().unwrap().search_path("note/grace").len(), 1);
+        assert_eq!(part.notes().len(), 4);
+
+        let mut senza_misura = TimeSignature::new(4, 4);
+        senza_misura.senza_misura = Some(String::new());
+        let before = part.clone();
+        assert!(matches!(part.rebar(&[]), Err(MashError::MissingTag { .. })));
+        assert!(matches!(part.rebar(&[senza_misura]), Err(MashError::InvalidValue { .. })));
+        assert!(matches!(part.rebar(&[TimeSignature::new(3, 4), TimeSignature::new(0, 4)]),
+                         Err(MashError::InvalidValue { .. })));
+        assert!(matches!(part.rebar(&[TimeSignature::new(3, 0)]), Err(MashError::InvalidValue { .. })));
+        assert_eq!(part, before);
     }
 
     fn measure_xml () -> &'static [u8] {
@@ -3,57 +3,153 @@ use crate::parser::xml_tag::XmlTag;
 use crate::msc::gnote::note_attr;
 use std::rc::Rc;
 use crate::msc::measure::Measure;
+use xml::attribute::OwnedAttribute;
+use std::path::Path;
+use crate::parser::timewise;
+use crate::parser::xml_document::XmlDocument;
+use crate::libs::error::{MashError, MashResult, Location};
 
-#[derive(Debug)]
-pub struct Stream<'a> {
+#[derive(Debug, Clone, PartialEq)]
+pub struct Stream {
+    // attributes of <score-partwise> such as version
+    _xml_attrs: Vec<OwnedAttribute>,
     // Contains tags that we don't really care about
-    _xml_tags: Vec< &'a XmlTag>,
+    _xml_tags: Vec< XmlTag>,
+    // declaration, DOCTYPE, comments and processing instructions of the file read,
+    // with an empty root. None when read from a tag
+    _xml_document: Option<XmlDocument>,
 
-    duration: note_attr::Duration,
-    parts: Vec< Part<'a>>
+    pub duration: note_attr::Duration,
+    pub parts: Vec< Part>
 }
 
-impl<'a> Stream<'a> {
-    fn from_xml_tag(xml_tag: &'a XmlTag) -> Self {
-        assert_eq!(xml_tag.name.local_name, "score-partwise");
+impl Stream {
+    pub fn from_xml_tag(xml_tag: &XmlTag) -> Self {
+        Self::try_from_xml_tag(xml_tag).unwrap()
+    }
+
+    /// Read the score at path, .musicxml or .mxl
+    pub fn from_file<T: AsRef<Path>>(path: T) -> Self {
+        Self::try_from_file(path).unwrap()
+    }
+
+    pub fn try_from_file<T: AsRef<Path>>(path: T) -> MashResult<Self> {
+        // the model owns everything it keeps, so the tree can go once parsed
+        let document = XmlDocument::try_from_file(path)?;
+        Self::try_from_document(&document)
+    }
+
+    pub fn from_document(document: &XmlDocument) -> Self {
+        Self::try_from_document(document).unwrap()
+    }
+
+    /// Read the root of document and keep what surrounds it, so write_to_file
+    /// gives the same prolog back
+    pub fn try_from_document(document: &XmlDocument) -> MashResult<Self> {
+        let mut stream = Self::try_from_xml_tag(&document.root)?;
+        stream._xml_document = Some(XmlDocument {
+            version: document.version,
+            encoding: document.encoding.clone(),
+            standalone: document.standalone,
+            prolog: document.prolog.clone(),
+            root: XmlTag::new(&document.root.name.local_name),
+            epilog: document.epilog.clone()
+        });
+        Ok(stream)
+    }
+
+    /// Read either a <score-partwise> or a <score-timewise> document
+    pub fn try_from_xml_tag(xml_tag: &XmlTag) -> MashResult<Self> {
+        let timewise = match xml_tag.name.local_name.as_str() {
+            "score-partwise" => false,
+            "score-timewise" => true,
+            _ => return Err(MashError::UnexpectedTag {
+                expected: "score-partwise".to_string(), location: Location::of(xml_tag)
+            })
+        };
         let mut stream = Stream {
+            _xml_attrs: xml_tag.attributes.clone(),
             _xml_tags: Vec::with_capacity(5),
+            _xml_document: None,
             duration: note_attr::Duration::from(0u16),
             parts: Vec::with_capacity(4),
         };
-        // Store extra_tags
+        // Store extra_tags in the order they are written back. <part-list> is kept
+        // whole since parts only need its ids
+        let mut extra_tags = Vec::with_capacity(5);
         XmlTag::push_extra_tags_to(&xml_tag,
-                                   &["identification", "defaults"],
-                                   &mut stream._xml_tags);
-        //Self::parse_part(&xml_tag);
+                                   &["work", "movement-number", "movement-title",
+                                     "identification", "defaults", "credit", "part-list"],
+                                   &mut extra_tags);
+        stream._xml_tags = extra_tags.into_iter().cloned().collect();
+        stream.parts = Self::parse_parts(xml_tag, timewise)?;
+        stream.duration = stream.parts
+            .iter()
+            .map(|part| part.duration.clone())
+            .max()
+            .unwrap_or(note_attr::Duration::from(0u16));
 
-        stream
+        Ok(stream)
+    }
+
+    /// Write the score as <score-partwise>
+    pub fn to_xml_tag(&self) -> MashResult<XmlTag> {
+        let mut builder = XmlTag::new_tag_builder();
+        let score = builder.add_tag("score-partwise");
+        score.add_attributes(&self._xml_attrs);
+        for tag in self._xml_tags.iter() {
+            score.direct_add_tag(tag.clone());
+        }
+        for part in self.parts.iter() {
+            score.direct_add_tag(part.to_xml_tag()?);
+        }
+        Ok(builder.built_tag())
+    }
+
+    /// Write the score as <score-timewise>
+    pub fn to_timewise_xml_tag(&self) -> MashResult<XmlTag> {
+        timewise::partwise_to_timewise(&self.to_xml_tag()?)
     }
 
-    // fn parse_part(xml_tag: &Rc< XmlTag>) -> Vec< Part> {
-    //     // Extract the <attribute> from the first measure and pass as arg to initialize
-    //     // every following measure
-    //     let attr_tag = XmlTag::search_tag(xml_tag, "attributes")
-    //         .pop_front().unwrap();
-    //
-    //     // create parts Vec with capacity as indicated by <staves>
-    //     let mut parts: Vec< Part> = Vec::with_capacity(
-    //         attrs.entry("staves".to_string())
-    //             .or_insert("1".to_string())
-    //             .parse::<usize>().unwrap()
-    //     );
-    //
-    //     for measure_tag in XmlTag::search_tag(xml_tag, "measure").into_iter() {
-    //         let measures = Measure::from_xml_tag(&measure_tag, &attr_tag);
-    //         parts.iter_mut()
-    //             .zip(measures.into_iter())
-    //             .for_each(
-    //                 |(part, measure)|
-    //                     part.measures.push(measure)
-    //             );
-    //     }
-    //     parts
-    // }
+    /// Write the score to path, as .mxl if the extension is .mxl. A score read from
+    /// a file gets the declaration, DOCTYPE and comments of that file back
+    pub fn write_to_file<T: AsRef<Path>>(&self, path: T) -> MashResult<()> {
+        let root = self.to_xml_tag()?;
+        match self._xml_document.as_ref() {
+            Some(document) => document.clone().with_root(root).write_to_file(path),
+            None => root.write_to_file(path)
+        }
+    }
+
+    /// Build one Part for every <score-part> in <part-list>, in the order they are
+    /// listed, from the <part> whose id matches. In timewise documents the part is
+    /// gathered from the <part> with that id in every <measure>
+    fn parse_parts(xml_tag: &XmlTag, timewise: bool) -> MashResult<Vec< Part>> {
+        let outer_name = if timewise { "measure" } else { "part" };
+        let outer_tags: Vec< &XmlTag> = xml_tag.child_tags
+            .iter()
+            .filter(|tag| tag.name.local_name == outer_name)
+            .collect();
+
+        xml_tag.search_path("part-list/score-part")
+            .iter()
+            .map(|score_part| {
+                let id = score_part.require_attribute_value("id")
+                    .map_err(|err| err.within(xml_tag))?;
+                if timewise {
+                    return Part::try_from_timewise_xml_tags(&id, &outer_tags)
+                        .map_err(|err| err.within(xml_tag));
+                }
+                let part_tag = outer_tags
+                    .iter()
+                    .find(|part_tag| part_tag.get_attribute_value("id").as_ref() == Some(&id))
+                    .ok_or_else(|| MashError::MissingTag {
+                        location: Location::of_path(xml_tag, &format!("part[@id=\"{}\"]", id))
+                    })?;
+                Part::try_from_xml_tag(part_tag).map_err(|err| err.within(xml_tag))
+            })
+            .collect()
+    }
 }
 
 mod tests {
@@ -67,4 +163,203 @@ mod tests {
         let f = Stream::from_xml_tag(&xml_tree);
         //println!("{:#?}", f);
     }
+
+    #[test]
+    fn test_from_file() {
+        fn open(path: &str) -> Stream {
+            Stream::from_file(path)
+        }
+        let stream = open("src/parser/test/example6.musicxml");
+        let expected = Stream::from_document(&XmlDocument::try_from_file("src/parser/test/example6.musicxml").unwrap());
+        // a Stream can be handed to another thread
+        let stream = std::thread::spawn(move || stream).join().unwrap();
+        assert_eq!(stream, expected);
+        assert!(Stream::try_from_file("src/parser/test/missing.musicxml").is_err());
+    }
+
+    #[test]
+    fn test_parts() {
+        let xml_tree = XmlTag::from_file("src/parser/test/example6.musicxml");
+        let stream = Stream::from_xml_tag(&xml_tree);
+
+        let ids: Vec<&str> = stream.parts.iter().map(|part| part.id.as_str()).collect();
+        assert_eq!(ids, vec!["P1", "P2"]);
+        assert!(stream.parts.iter().all(|part| part.measures.len() == 6));
+        // six measures of 4/4 in quarter notes
+        assert!(stream.duration == note_attr::Duration::from(24u32));
+        // <work>, <identification>, <defaults>, <credit> and <part-list>
+        assert_eq!(stream._xml_tags.len(), 5);
+    }
+
+    #[test]
+    fn test_nested_header_tags() {
+        let xml_tree = XmlTag::from_buffer(r#"<score-partwise version="4.0">
+  <identification><creator type="composer">A</creator></identification>
+  <part-list>
+    <score-part id="P1">
+      <part-name>Voice</part-name>
+      <identification><creator type="lyricist">B</creator></identification>
+      </score-part>
+    </part-list>
+  <part id="P1">
+    <measure number="1">
+      <attributes><divisions>1</divisions></attributes>
+      <note><rest/><duration>4</duration></note>
+      </measure>
+    </part>
+  </score-partwise>"#.as_bytes());
+        let stream = Stream::from_xml_tag(&xml_tree);
+        // the <identification> of the score-part stays inside <part-list>
+        let names: Vec< &str> = stream._xml_tags.iter().map(|tag| tag.name.local_name.as_str()).collect();
+        assert_eq!(names, vec!["identification", "part-list"]);
+        let written = stream.to_xml_tag().unwrap();
+        assert_eq!(written.child_tags.iter().filter(|tag| tag.name.local_name == "identification").count(), 1);
+        assert!(written.path_exists("part-list/score-part/identification"));
+    }
+
+    #[test]
+    fn test_round_trip() {
+        let xml_tree = XmlTag::from_file("src/parser/test/example6.musicxml");
+        let stream = Stream::from_xml_tag(&xml_tree);
+
+        let mut buffer: Vec<u8> = Vec::new();
+        stream.to_xml_tag().unwrap().write_to_buffer(&mut buffer).unwrap();
+        let written_tree = XmlTag::from_buffer(buffer.as_slice());
+        let written_stream = Stream::from_xml_tag(&written_tree);
+
+        assert_eq!(without_divisions(&written_stream), without_divisions(&stream));
+    }
+
+    #[test]
+    fn test_round_trip_rests_lyrics() {
+        let xml_tree = XmlTag::from_file("src/parser/test/rests_lyrics.musicxml");
+        let stream = Stream::from_xml_tag(&xml_tree);
+
+        let mut buffer: Vec<u8> = Vec::new();
+        stream.to_xml_tag().unwrap().write_to_buffer(&mut buffer).unwrap();
+        let written_tree = XmlTag::from_buffer(buffer.as_slice());
+        assert_eq!(without_divisions(&Stream::from_xml_tag(&written_tree)), without_divisions(&stream));
+
+        let rests: Vec<_> = written_tree.search_path("part/measure/note/rest").into_iter().collect();
+        assert_eq!(rests[0].get_attribute_value("measure").as_deref(), Some("yes"));
+        assert!(rests[1].path_exists("display-step") && rests[1].path_exists("display-octave"));
+        let lyrics: Vec<_> = written_tree.search_path("part/measure/note/lyric").into_iter().collect();
+        let numbers: Vec<_> = lyrics.iter().map(|lyric| lyric.get_attribute_value("number").unwrap()).collect();
+        assert_eq!(numbers, vec!["1", "verse2", "1", "verse2"]);
+        assert!(lyrics.iter().all(|lyric| lyric.get_attribute_value("name").as_deref() == Some("verse")));
+        assert_eq!(lyrics[1].count_tag("text"), 2);
+        assert!(lyrics[1].path_exists("elision") && lyrics[2].path_exists("extend"));
+        assert!(!lyrics[3].path_exists("text") && lyrics[3].path_exists("extend"));
+    }
+
+    #[test]
+    fn test_round_trip_comments() {
+        let xml_tree = XmlTag::from_buffer(r#"<score-partwise version="4.0">
+  <part-list><score-part id="P1"><part-name>Voice</part-name></score-part></part-list>
+  <part id="P1">
+    <measure number="1">
+      <attributes>
+        <divisions>1</divisions>
+        <key><!--k--><fifths>-2</fifths><mode>major</mode></key>
+        <time><beats>3</beats><!--t--><beat-type>4</beat-type></time>
+        </attributes>
+      <note>
+        <pitch><step>B</step><alter>-1</alter><octave>4</octave></pitch>
+        <!-- after the pitch -->
+        <duration>3</duration>
+        <type>half</type>
+        <dot/>
+        <lyric><!--l--><text>Ah</text></lyric>
+        <!--n-->
+        </note>
+      </measure>
+    </part>
+  </score-partwise>"#.as_bytes());
+        let stream = Stream::from_xml_tag(&xml_tree);
+
+        let write = |stream: &Stream| {
+            let mut buffer: Vec<u8> = Vec::new();
+            stream.to_xml_tag().unwrap().write_to_buffer(&mut buffer).unwrap();
+            String::from_utf8(buffer).unwrap()
+        };
+        let written = write(&stream);
+        let written_stream = Stream::from_xml_tag(&XmlTag::from_buffer(written.as_bytes()));
+        assert_eq!(without_divisions(&written_stream), without_divisions(&stream));
+        // comments are written as read, where they were read
+        assert_eq!(write(&written_stream), written);
+        for comment in ["<key>\n          <!--k-->\n          <fifths>",
+                        "<beats>3</beats>\n          <!--t-->\n          <beat-type>",
+                        "</pitch>\n        <!-- after the pitch -->\n        <duration>",
+                        "<lyric>\n          <!--l-->\n          <text>",
+                        "</lyric>\n        <!--n-->\n      </note>"].iter() {
+            assert!(written.contains(comment), "{} not in {}", comment, written);
+        }
+    }
+
+    #[test]
+    fn test_timewise() {
+        let xml_tree = XmlTag::from_file("src/parser/test/example6.musicxml");
+        let stream = Stream::from_xml_tag(&xml_tree);
+
+        let timewise_tree = stream.to_timewise_xml_tag().unwrap();
+        assert_eq!(timewise_tree.name.local_name, "score-timewise");
+        let timewise_stream = Stream::from_xml_tag(&timewise_tree);
+        assert_eq!(timewise_stream.parts.len(), 2);
+        assert_eq!(without_divisions(&timewise_stream), without_divisions(&stream));
+    }
+
+    #[test]
+    fn test_mxl_file() {
+        let xml_tree = XmlTag::from_file("src/parser/test/example6.musicxml");
+        let stream = Stream::from_xml_tag(&xml_tree);
+        let path = std::env::temp_dir().join("mash_test_stream.mxl");
+        stream.write_to_file(&path).unwrap();
+
+        let mxl_tree = XmlTag::try_from_file(&path).unwrap();
+        assert_eq!(without_divisions(&Stream::from_xml_tag(&mxl_tree)), without_divisions(&stream));
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_write_prolog() {
+        let document = XmlDocument::try_from_buffer(r#"<?xml version="1.0" encoding="UTF-8" standalone="no"?>
+<!-- exported by hand -->
+<!DOCTYPE score-partwise PUBLIC "-//Recordare//DTD MusicXML 3.0 Partwise//EN" "http://www.musicxml.org/dtds/partwise.dtd">
+<?editor keep-layout?>
+<score-partwise version="3.1">
+  <part-list><score-part id="P1"><part-name>Voice</part-name></score-part></part-list>
+  <part id="P1">
+    <measure number="1">
+      <attributes><divisions>1</divisions></attributes>
+      <note><rest/><duration>4</duration></note>
+      </measure>
+    </part>
+  </score-partwise>
+<!-- trailing -->"#.as_bytes()).unwrap();
+        let stream = Stream::from_document(&document);
+        let path = std::env::temp_dir().join("mash_test_stream_prolog.musicxml");
+        stream.write_to_file(&path).unwrap();
+
+        let written = XmlDocument::try_from_file(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        // the DOCTYPE says 3.0 where a new one would follow version="3.1"
+        assert_eq!(written.doctype(), document.doctype());
+        assert_eq!(written.prolog, document.prolog);
+        assert_eq!(written.epilog, document.epilog);
+        assert_eq!(written.standalone, Some(false));
+        assert_eq!(Stream::from_document(&written), stream);
+    }
+
+    /// stream without the divisions of its parts. Parts are written with the smallest
+    /// divisions they need, so a score read back may have other ones
+    fn without_divisions(stream: &Stream) -> Stream {
+        let mut stream = stream.clone();
+        for part in stream.parts.iter_mut() {
+            for change in part.timeline.values_mut() {
+                change.divisions = None;
+            }
+            part.timeline.retain(|_, change| !change.is_empty());
+        }
+        stream
+    }
 }
\ No newline at end of file
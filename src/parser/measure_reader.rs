@@ -0,0 +1,229 @@
+//! Pull reader that hands out a score one measure at a time.
+//! XmlTag::from_buffer keeps the whole document in memory. MeasureReader instead
+//! builds only the <measure> being read and drops it once the caller is done, so
+//! memory stays bounded by the largest measure plus the score header.
+//! Both layouts give the same chunks: for score-timewise the <part> content is moved
+//! under its <measure>, which is what a score-partwise measure looks like.
+use std::io::Read;
+use std::fs::File;
+use std::path::Path;
+use std::collections::LinkedList;
+use xml::{EventReader, ParserConfig};
+use xml::common::Position;
+use xml::attribute::OwnedAttribute;
+use xml::reader::XmlEvent;
+use crate::parser::xml_tag::XmlTag;
+use crate::libs::error::{MashError, MashResult, Location};
+
+/// One measure of one part
+#[derive(Debug, Clone, PartialEq)]
+pub struct MeasureChunk {
+    pub part_id: String,
+    // <measure> with its attributes and the part's content for that measure
+    pub measure: XmlTag,
+}
+
+pub struct MeasureReader<R: Read> {
+    reader: EventReader<R>,
+    // root tag holding every child read so far that is not a part or measure,
+    // e.g. <work> and <part-list>. None until the root tag is read
+    header: Option<XmlTag>,
+    timewise: bool,
+    // attributes of the open depth 1 tag: <part> in partwise, <measure> in timewise
+    outer_attrs: Vec< OwnedAttribute>,
+    // tags being built, outermost first
+    stack: Vec< XmlTag>,
+    // depth of the next start tag, the root is 0
+    depth: usize,
+    done: bool,
+}
+
+impl MeasureReader<File> {
+    /// Stream a .musicxml/.xml file. .mxl archives have to be read whole
+    pub fn from_file<T: AsRef<Path>>(path: T) -> MashResult<Self> {
+        Ok(Self::new(File::open(path)?))
+    }
+}
+
+impl<R: Read> MeasureReader<R> {
+    pub fn new(buffer: R) -> Self {
+        MeasureReader {
+            reader: ParserConfig::new()
+                .ignore_comments(false)
+                .create_reader(buffer),
+            header: None,
+            timewise: false,
+            outer_attrs: Vec::new(),
+            stack: Vec::new(),
+            depth: 0,
+            done: false
+        }
+    }
+
+    /// The root tag with the header read so far. Everything before the first part
+    /// (partwise) or measure (timewise) is there once the first chunk is returned
+    pub fn header(&self) -> Option<&XmlTag> {
+        self.header.as_ref()
+    }
+
+    /// Read up to the end of the next measure
+    fn read_chunk(&mut self) -> MashResult<Option<MeasureChunk>> {
+        let (outer_name, inner_name) = if self.timewise { ("measure", "part") } else { ("part", "measure") };
+        loop {
+            match self.reader.next()? {
+                XmlEvent::StartElement { name, attributes, .. } => {
+                    let depth = self.depth;
+                    self.depth += 1;
+                    let tag = XmlTag {
+                        name, attributes,
+                        text: None,
+                        position: Some(self.reader.position()),
+                        child_tags: LinkedList::new()
+                    };
+                    if depth == 0 {
+                        match tag.name.local_name.as_str() {
+                            "score-partwise" => self.timewise = false,
+                            "score-timewise" => self.timewise = true,
+                            _ => return Err(MashError::UnexpectedTag {
+                                expected: "score-partwise".to_string(), location: Location::of(&tag)
+                            })
+                        }
+                        self.header = Some(tag);
+                        return self.read_chunk();
+                    }
+                    if depth == 1 && self.stack.is_empty() && tag.name.local_name == outer_name {
+                        // opened, not built: its children are handed out one by one
+                        self.outer_attrs = tag.attributes;
+                        continue;
+                    }
+                    self.stack.push(tag);
+                }
+
+                XmlEvent::EndElement { .. } => {
+                    self.depth -= 1;
+                    let built = match self.stack.pop() {
+                        Some(built) => built,
+                        // end of the root or of a part/measure we only opened
+                        None => continue
+                    };
+                    if let Some(parent) = self.stack.last_mut() {
+                        parent.child_tags.push_back(built);
+                    } else if self.depth == 1 {
+                        // header tag directly under the root
+                        if let Some(header) = self.header.as_mut() {
+                            header.child_tags.push_back(built);
+                        }
+                    } else if built.name.local_name == inner_name {
+                        return Ok(Some(self.chunk(built)?));
+                    }
+                    // anything else directly inside a part/measure is dropped
+                }
+
+                XmlEvent::Characters(text) => {
+                    // text split by a comment arrives in pieces
+                    if let Some(last) = self.stack.last_mut() {
+                        match last.text.as_mut() {
+                            Some(before) => before.push_str(&text),
+                            None => last.text = Some(text)
+                        }
+                    }
+                }
+
+                XmlEvent::Comment(text) => {
+                    if let Some(last) = self.stack.last_mut() {
+                        last.child_tags.push_back(XmlTag::new_comment(&text));
+                    }
+                }
+
+                XmlEvent::EndDocument => {
+                    return if self.header.is_some() { Ok(None) } else { Err(MashError::UnexpectedEof) };
+                }
+
+                _ => {}
+            }
+        }
+    }
+
+    /// Turn the inner tag just read into a chunk
+    fn chunk(&self, inner: XmlTag) -> MashResult<MeasureChunk> {
+        let (part_attrs, measure) = if self.timewise {
+            let measure = XmlTag {
+                name: XmlTag::new("measure").name,
+                attributes: self.outer_attrs.clone(),
+                text: None,
+                position: inner.position,
+                child_tags: inner.child_tags
+            };
+            (&inner.attributes, measure)
+        } else {
+            (&self.outer_attrs, inner)
+        };
+        let part_id = part_attrs
+            .iter()
+            .find(|attr| attr.name.local_name == "id")
+            .map(|attr| attr.value.clone())
+            .ok_or_else(|| MashError::MissingAttribute {
+                name: "id".to_string(), location: Location::of_path(&measure, "part")
+            })?;
+        Ok(MeasureChunk { part_id, measure })
+    }
+}
+
+impl<R: Read> Iterator for MeasureReader<R> {
+    type Item = MashResult<MeasureChunk>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.done {
+            return None;
+        }
+        let chunk = self.read_chunk().transpose();
+        // stop after the last measure or the first error
+        self.done = !matches!(chunk, Some(Ok(_)));
+        chunk
+    }
+}
+
+mod tests {
+    use super::*;
+    use crate::parser::timewise::partwise_to_timewise;
+
+    #[test]
+    fn test_partwise() {
+        let xml_tree = XmlTag::from_file("src/parser/test/example6.musicxml");
+        let mut reader = MeasureReader::from_file("src/parser/test/example6.musicxml").unwrap();
+        let first = reader.next().unwrap().unwrap();
+        assert_eq!(first.part_id, "P1");
+        assert_eq!(&first.measure, xml_tree.search_path_unique("part/measure").unwrap());
+        assert!(reader.header().unwrap().path_exists("part-list/score-part"));
+
+        let rest: Vec< MeasureChunk> = reader.map(|chunk| chunk.unwrap()).collect();
+        assert_eq!(rest.len(), 11);
+        assert_eq!(rest.last().unwrap().part_id, "P2");
+        assert_eq!(rest.last().unwrap().measure.get_attribute_value("number"), Some("6".to_string()));
+    }
+
+    #[test]
+    fn test_timewise() {
+        let partwise = XmlTag::from_file("src/parser/test/example6.musicxml");
+        let mut buffer = Vec::new();
+        partwise_to_timewise(&partwise).unwrap().write_to_buffer(&mut buffer).unwrap();
+
+        let mut chunks: Vec< MeasureChunk> = MeasureReader::new(buffer.as_slice())
+            .map(|chunk| chunk.unwrap())
+            .collect();
+        // timewise order is measure by measure, partwise part by part
+        assert_eq!(chunks[1].part_id, "P2");
+        chunks.sort_by_key(|chunk| chunk.part_id.clone());
+        let expected: Vec< MeasureChunk> = MeasureReader::from_file("src/parser/test/example6.musicxml").unwrap()
+            .map(|chunk| chunk.unwrap())
+            .collect();
+        assert_eq!(chunks, expected);
+    }
+
+    #[test]
+    fn test_wrong_root() {
+        let mut reader = MeasureReader::new("<container><rootfiles/></container>".as_bytes());
+        assert!(matches!(reader.next(), Some(Err(MashError::UnexpectedTag { .. }))));
+        assert!(reader.next().is_none());
+    }
+}
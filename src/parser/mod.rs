@@ -4,3 +4,10 @@
 #![allow(non_camel_case_types)]
 
 pub mod xml_tag;
+pub mod xml_document;
+pub mod query;
+pub mod measure_reader;
+pub mod xml_arena;
+pub mod schema_order;
+pub mod mxl;
+pub mod timewise;
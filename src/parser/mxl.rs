@@ -0,0 +1,114 @@
+//! Compressed MusicXML (.mxl). An .mxl file is a zip archive holding the score,
+//! a META-INF/container.xml that points to it and an uncompressed "mimetype" entry
+//! stored first.
+use std::io::{Read, Write, Seek};
+use std::path::Path;
+use zip::{ZipArchive, ZipWriter, CompressionMethod};
+use zip::write::FileOptions;
+use crate::parser::xml_tag::XmlTag;
+use crate::parser::xml_document::XmlDocument;
+use crate::libs::error::{MashError, MashResult};
+
+pub const MIMETYPE: &str = "application/vnd.recordare.musicxml";
+pub const ROOTFILE_MEDIA_TYPE: &str = "application/vnd.recordare.musicxml+xml";
+const CONTAINER_PATH: &str = "META-INF/container.xml";
+// name of the score inside archives we write
+const ROOTFILE_PATH: &str = "score.musicxml";
+
+/// True if path should be read and written as .mxl
+pub fn is_mxl_path<T: AsRef<Path>>(path: T) -> bool {
+    path.as_ref()
+        .extension()
+        .is_some_and(|ext| ext.eq_ignore_ascii_case("mxl"))
+}
+
+/// Read the root score of an .mxl archive, following META-INF/container.xml
+pub fn read_mxl(reader: impl Read + Seek) -> MashResult<XmlTag> {
+    read_mxl_document(reader).map(|document| document.root)
+}
+
+/// Like read_mxl but keeps the prolog of the score file, see XmlDocument
+pub fn read_mxl_document(reader: impl Read + Seek) -> MashResult<XmlDocument> {
+    let mut archive = ZipArchive::new(reader)?;
+    let container = XmlTag::try_from_buffer(archive.by_name(CONTAINER_PATH)?)?;
+    // The first <rootfile> is the score, later ones are alternate renditions
+    let rootfile = container.require_tag("rootfiles/rootfile")?;
+    let full_path = rootfile.require_attribute_value("full-path")
+        .map_err(|err| err.within(&container))?;
+    let score = archive.by_name(&full_path)?;
+    XmlDocument::try_from_buffer(score)
+}
+
+/// Package xml_tag, usually <score-partwise>, as an .mxl archive
+pub fn write_mxl(xml_tag: &XmlTag, writer: impl Write + Seek) -> MashResult<()> {
+    write_mxl_document(&XmlDocument::new(xml_tag.clone()), writer)
+}
+
+/// Package a whole document as an .mxl archive
+pub fn write_mxl_document(document: &XmlDocument, writer: impl Write + Seek) -> MashResult<()> {
+    let mut archive = ZipWriter::new(writer);
+    // mimetype must come first and stay uncompressed so it can be sniffed
+    archive.start_file("mimetype",
+                       FileOptions::default().compression_method(CompressionMethod::Stored))?;
+    archive.write_all(MIMETYPE.as_bytes())?;
+
+    let deflated = FileOptions::default().compression_method(CompressionMethod::Deflated);
+    archive.start_file(CONTAINER_PATH, deflated)?;
+    container_xml_tag().write_to_buffer(&mut archive)?;
+
+    archive.start_file(ROOTFILE_PATH, deflated)?;
+    document.write_to_buffer(&mut archive)?;
+
+    archive.finish()?;
+    Ok(())
+}
+
+/// <container> pointing to the score we write
+fn container_xml_tag() -> XmlTag {
+    let mut builder = XmlTag::new_tag_builder();
+    builder.add_tag("container")
+        .add_tag("rootfiles")
+        .add_tag("rootfile")
+        .add_attribute("full-path", ROOTFILE_PATH)
+        .add_attribute("media-type", ROOTFILE_MEDIA_TYPE);
+    builder.built_tag()
+}
+
+mod tests {
+    use super::*;
+    use std::io::Cursor;
+
+    #[test]
+    fn test_round_trip() {
+        let xml_tree = XmlTag::from_file("src/parser/test/example6.musicxml");
+        let mut buffer = Cursor::new(Vec::new());
+        write_mxl(&xml_tree, &mut buffer).unwrap();
+
+        // mimetype is the first local file entry, stored uncompressed
+        let bytes = buffer.into_inner();
+        assert_eq!(&bytes[30..38], b"mimetype");
+        assert_eq!(&bytes[38..38 + MIMETYPE.len()], MIMETYPE.as_bytes());
+
+        let read_tree = read_mxl(Cursor::new(bytes)).unwrap();
+        assert_eq!(read_tree, xml_tree);
+    }
+
+    #[test]
+    fn test_missing_container() {
+        let mut buffer = Cursor::new(Vec::new());
+        let mut archive = ZipWriter::new(&mut buffer);
+        archive.start_file("score.xml", FileOptions::default()).unwrap();
+        archive.finish().unwrap();
+        drop(archive);
+        match read_mxl(buffer) {
+            Err(MashError::Zip(_)) => {}
+            other => panic!("unexpected result {:?}", other.map(|_| ()))
+        }
+    }
+
+    #[test]
+    fn test_is_mxl_path() {
+        assert!(is_mxl_path("scores/example.MXL"));
+        assert!(!is_mxl_path("src/parser/test/example6.musicxml"));
+    }
+}
@@ -0,0 +1,266 @@
+//! A small XPath-like query language over XmlTag.
+//! A query is a list of steps, each a tag name with optional predicates:
+//!     part/measure[2]/note            children, from the tag the query runs on
+//!     //note/pitch/step/text()        <note> anywhere below, then text of <step>
+//!     attributes/clef[@number="2"]    attribute predicate, [@number] tests presence
+//!     measure[@number="3"]/note[1]    predicates apply in order, positions count from 1
+//! '/' selects direct children and '//' descendants at any depth. A name of '*'
+//! matches every tag. As in XPath, [n] counts among the children of one parent, so
+//! //note[1] is the first note of every parent holding notes.
+//! Compile a query once with Query::new and run it on as many tags as needed.
+use std::str::FromStr;
+use std::collections::HashSet;
+use crate::parser::xml_tag::XmlTag;
+use crate::libs::error::{MashError, MashResult};
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Query {
+    steps: Vec< Step>,
+    text: bool, // ends with text()
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct Step {
+    descendant: bool, // reached through '//' rather than '/'
+    name: String,
+    predicates: Vec< Predicate>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Predicate {
+    // [@name] or [@name="value"]
+    Attribute { name: String, value: Option<String> },
+    // [n], counted from 1
+    Position(usize),
+}
+
+impl Query {
+    pub fn new(query: &str) -> MashResult<Self> {
+        let invalid = |reason: &str| MashError::InvalidQuery {
+            query: query.to_string(), reason: reason.to_string()
+        };
+        let mut steps = Vec::new();
+        let mut text = false;
+        let mut rest = query.trim();
+        if rest.is_empty() {
+            return Err(invalid("empty query"));
+        }
+        while !rest.is_empty() {
+            if text {
+                return Err(invalid("text() must be the last step"));
+            }
+            let descendant = rest.starts_with("//");
+            rest = rest.trim_start_matches('/');
+
+            let name_end = rest.find(['/', '[']).unwrap_or(rest.len());
+            let name = &rest[..name_end];
+            rest = &rest[name_end..];
+            if name == "text()" {
+                if descendant || rest.starts_with('[') {
+                    return Err(invalid("text() takes no '//' or predicates"));
+                }
+                text = true;
+                continue;
+            }
+            if name.is_empty() || name.contains(|c: char| c.is_whitespace() || "\"'@()=]".contains(c)) {
+                return Err(invalid(&format!("\"{}\" is not a tag name", name)));
+            }
+
+            let mut predicates = Vec::new();
+            while rest.starts_with('[') {
+                let (predicate, after) = Self::parse_predicate(&rest[1..]).map_err(|reason| invalid(&reason))?;
+                predicates.push(predicate);
+                rest = after;
+            }
+            if !rest.is_empty() && !rest.starts_with('/') {
+                return Err(invalid(&format!("unexpected \"{}\"", rest)));
+            }
+            steps.push(Step { descendant, name: name.to_string(), predicates });
+        }
+        if steps.is_empty() {
+            return Err(invalid("text() needs a tag to read from"));
+        }
+        Ok(Query { steps, text })
+    }
+
+    /// Parse what follows '[' up to and including the closing ']'.
+    /// Returns the predicate and the rest of the query
+    fn parse_predicate(predicate: &str) -> Result<(Predicate, &str), String> {
+        let predicate = predicate.trim_start();
+        if let Some(attribute) = predicate.strip_prefix('@') {
+            let name_end = attribute.find(['=', ']'])
+                .ok_or("unclosed '['")?;
+            let name = attribute[..name_end].trim();
+            if name.is_empty() {
+                return Err("missing attribute name after '@'".to_string());
+            }
+            let mut rest = &attribute[name_end..];
+            let mut value = None;
+            if let Some(quoted) = rest.strip_prefix('=') {
+                let quoted = quoted.trim_start();
+                let quote = quoted.chars().next()
+                    .filter(|c| *c == '"' || *c == '\'')
+                    .ok_or("attribute value must be quoted")?;
+                let value_end = quoted[1..].find(quote).ok_or("unclosed quote")? + 1;
+                value = Some(quoted[1..value_end].to_string());
+                rest = quoted[value_end + 1..].trim_start();
+            }
+            let rest = rest.strip_prefix(']').ok_or("expected ']'")?;
+            Ok((Predicate::Attribute { name: name.to_string(), value }, rest))
+        } else {
+            let end = predicate.find(']').ok_or("unclosed '['")?;
+            let position: usize = predicate[..end].trim().parse()
+                .ok()
+                .filter(|position| *position > 0)
+                .ok_or_else(|| format!("\"{}\" is not a position", &predicate[..end]))?;
+            Ok((Predicate::Position(position), &predicate[end + 1..]))
+        }
+    }
+
+    /// Every tag the query selects from xml_tag. A query ending with text() keeps
+    /// only the tags that have text
+    pub fn select<'a>(&self, xml_tag: &'a XmlTag) -> Vec< &'a XmlTag> {
+        let mut context = vec![xml_tag];
+        for step in self.steps.iter() {
+            let mut selected = Vec::new();
+            for tag in context {
+                if step.descendant {
+                    Self::for_each_descendant_or_self(tag, &mut |parent| step.select_children(parent, &mut selected));
+                } else {
+                    step.select_children(tag, &mut selected);
+                }
+            }
+            if step.descendant {
+                // nested contexts can reach the same tag twice
+                let mut seen = HashSet::new();
+                selected.retain(|tag| seen.insert(*tag as *const XmlTag));
+            }
+            context = selected;
+        }
+        if self.text {
+            context.retain(|tag| tag.text.is_some());
+        }
+        context
+    }
+
+    /// The first tag the query selects
+    pub fn select_first<'a>(&self, xml_tag: &'a XmlTag) -> Option<&'a XmlTag> {
+        self.select(xml_tag).into_iter().next()
+    }
+
+    /// Text of every selected tag that has some
+    pub fn select_text<'a>(&self, xml_tag: &'a XmlTag) -> Vec< &'a str> {
+        self.select(xml_tag)
+            .into_iter()
+            .filter_map(|tag| tag.text.as_deref())
+            .collect()
+    }
+
+    fn for_each_descendant_or_self<'a>(xml_tag: &'a XmlTag, visit: &mut impl FnMut(&'a XmlTag)) {
+        visit(xml_tag);
+        for child_tag in xml_tag.child_tags.iter() {
+            Self::for_each_descendant_or_self(child_tag, visit);
+        }
+    }
+}
+
+impl FromStr for Query {
+    type Err = MashError;
+
+    fn from_str(query: &str) -> MashResult<Self> {
+        Self::new(query)
+    }
+}
+
+impl Step {
+    /// Push the children of parent this step selects to sink
+    fn select_children<'a>(&self, parent: &'a XmlTag, sink: &mut Vec< &'a XmlTag>) {
+        let mut candidates: Vec< &XmlTag> = parent.child_tags
+            .iter()
+            .filter(|tag| tag.is_element() && (self.name == "*" || tag.name.local_name == self.name))
+            .collect();
+        for predicate in self.predicates.iter() {
+            match predicate {
+                Predicate::Attribute { name, value } => candidates.retain(|tag| {
+                    match (tag.get_attribute_value(name), value) {
+                        (Some(found), Some(value)) => &found == value,
+                        (found, None) => found.is_some(),
+                        (None, _) => false
+                    }
+                }),
+                Predicate::Position(position) => {
+                    candidates = candidates.get(position - 1).map_or(Vec::new(), |tag| vec![*tag])
+                }
+            }
+        }
+        sink.extend(candidates);
+    }
+}
+
+mod tests {
+    use super::*;
+
+    fn xml_attributes() -> &'static [u8] {
+        r#"
+            <attributes>
+                <staves>2</staves>
+                <clef number="1">
+                  <sign>G</sign>
+                  <line>2</line>
+                </clef>
+                <clef number="2">
+                  <sign>F</sign>
+                  <line>4</line>
+                </clef>
+                <staff-details number="2">
+                  <staff-lines>5</staff-lines>
+                </staff-details>
+            </attributes>
+        "#.as_bytes()
+    }
+
+    #[test]
+    fn test_child_and_predicates() {
+        let xml_tree = XmlTag::from_buffer(xml_attributes());
+        let sign: Query = r#"clef[@number="2"]/sign/text()"#.parse().unwrap();
+        assert_eq!(sign.select_text(&xml_tree), vec!["F"]);
+        assert_eq!(Query::new("clef[2]/line").unwrap().select_text(&xml_tree), vec!["4"]);
+        assert_eq!(Query::new("clef[@number]").unwrap().select(&xml_tree).len(), 2);
+        assert_eq!(Query::new("*[@number='2']").unwrap().select(&xml_tree).len(), 2);
+        assert!(Query::new("clef[3]").unwrap().select_first(&xml_tree).is_none());
+        // '/' only looks one level down, unlike search_path
+        assert!(Query::new("sign").unwrap().select(&xml_tree).is_empty());
+        assert_eq!(Query::new("//sign").unwrap().select_text(&xml_tree), vec!["G", "F"]);
+    }
+
+    #[test]
+    fn test_score() {
+        let xml_tree = XmlTag::from_file("src/parser/test/example6.musicxml");
+        let notes = Query::new(r#"part[@id="P1"]/measure[1]/note"#).unwrap();
+        assert_eq!(notes.select(&xml_tree).len(),
+                   xml_tree.search_path("part").front().unwrap()
+                       .search_path("measure").front().unwrap()
+                       .child_tags.iter().filter(|tag| tag.name.local_name == "note").count());
+        // one first note per measure of every part
+        let first_notes = Query::new("//measure/note[1]").unwrap();
+        assert_eq!(first_notes.select(&xml_tree).len(), 12);
+        assert_eq!(Query::new("part//measure").unwrap().select(&xml_tree).len(), 12);
+        // one query run on every measure
+        let pitched = Query::new("note/pitch").unwrap();
+        let per_measure: usize = xml_tree.search_path("part/measure")
+            .iter()
+            .map(|measure| measure.select_with(&pitched).len())
+            .sum();
+        assert_eq!(per_measure, xml_tree.search_path("part/measure/note/pitch").len());
+    }
+
+    #[test]
+    fn test_invalid() {
+        for query in ["", "clef[", "clef[0]", "clef[@number=2]", "clef/text()/sign", "a b", "/text()"].iter() {
+            match Query::new(query) {
+                Err(MashError::InvalidQuery { .. }) => {}
+                other => panic!("{:?} compiled to {:?}", query, other)
+            }
+        }
+    }
+}
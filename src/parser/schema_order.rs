@@ -0,0 +1,164 @@
+//! Child element order required by the MusicXML 3.1/4.0 schema.
+//! Most MusicXML elements are xs:sequence, so their children are only valid in one
+//! order. Builders may add children in any order; XmlTag::order_by_schema then
+//! sorts them using the table below.
+use std::collections::HashMap;
+use crate::parser::xml_tag::XmlTag;
+
+lazy_static! {
+    /// Maps an element to the order of its children. Names joined by '|' share a
+    /// rank and keep the order they were added in, which covers repeated groups such
+    /// as (beats, beat-type)+ or (key-step, key-alter, key-accidental)*.
+    /// Elements with choice content, e.g. <measure> or <notations>, are left out
+    /// and never reordered.
+    pub static ref CHILD_ORDER: HashMap<&'static str, Vec<&'static str>> = {
+        let mut map = HashMap::new();
+        // score header
+        map.insert("score-partwise", vec![
+            "work", "movement-number", "movement-title", "identification", "defaults",
+            "credit", "part-list", "part"
+        ]);
+        map.insert("score-timewise", vec![
+            "work", "movement-number", "movement-title", "identification", "defaults",
+            "credit", "part-list", "measure"
+        ]);
+        map.insert("work", vec!["work-number", "work-title", "opus"]);
+        map.insert("identification", vec![
+            "creator", "rights", "encoding", "source", "relation", "miscellaneous"
+        ]);
+        map.insert("defaults", vec![
+            "scaling", "concert-score", "page-layout", "system-layout", "staff-layout",
+            "appearance", "music-font", "word-font", "lyric-font", "lyric-language"
+        ]);
+        map.insert("scaling", vec!["millimeters", "tenths"]);
+        map.insert("score-part", vec![
+            "identification", "part-link", "part-name", "part-name-display",
+            "part-abbreviation", "part-abbreviation-display", "group",
+            "score-instrument", "player", "midi-device|midi-instrument"
+        ]);
+        map.insert("score-instrument", vec![
+            "instrument-name", "instrument-abbreviation", "instrument-sound",
+            "solo|ensemble", "virtual-instrument"
+        ]);
+        map.insert("midi-instrument", vec![
+            "midi-channel", "midi-name", "midi-bank", "midi-program", "midi-unpitched",
+            "volume", "pan", "elevation"
+        ]);
+        // layout
+        map.insert("print", vec![
+            "page-layout", "system-layout", "staff-layout", "measure-layout",
+            "measure-numbering", "part-name-display", "part-abbreviation-display"
+        ]);
+        map.insert("page-layout", vec!["page-height", "page-width", "page-margins"]);
+        map.insert("page-margins", vec!["left-margin", "right-margin", "top-margin", "bottom-margin"]);
+        map.insert("system-layout", vec![
+            "system-margins", "system-distance", "top-system-distance", "system-dividers"
+        ]);
+        map.insert("system-margins", vec!["left-margin", "right-margin"]);
+        map.insert("staff-layout", vec!["staff-distance"]);
+        // attributes
+        map.insert("attributes", vec![
+            "footnote", "level", "divisions", "key", "time", "staves", "part-symbol",
+            "instruments", "clef", "staff-details", "transpose|for-part", "directive",
+            "measure-style"
+        ]);
+        map.insert("key", vec![
+            "cancel", "fifths", "mode", "key-step|key-alter|key-accidental", "key-octave"
+        ]);
+        map.insert("time", vec!["beats|beat-type", "interchangeable", "senza-misura"]);
+        map.insert("clef", vec!["sign", "line", "clef-octave-change"]);
+        map.insert("staff-details", vec![
+            "staff-type", "staff-lines", "line-detail", "staff-tuning", "capo", "staff-size"
+        ]);
+        // measure content
+        map.insert("note", vec![
+            "grace", "cue", "chord", "pitch|unpitched|rest", "duration", "tie",
+            "instrument", "footnote", "level", "voice", "type", "dot", "accidental",
+            "time-modification", "stem", "notehead", "notehead-text", "staff", "beam",
+            "notations", "lyric", "play", "listen"
+        ]);
+        map.insert("pitch", vec!["step", "alter", "octave"]);
+        map.insert("unpitched", vec!["display-step", "display-octave"]);
+        map.insert("rest", vec!["display-step", "display-octave"]);
+        map.insert("time-modification", vec![
+            "actual-notes", "normal-notes", "normal-type", "normal-dot"
+        ]);
+        map.insert("lyric", vec![
+            "syllabic|text|elision|extend|laughing|humming", "end-line", "end-paragraph",
+            "footnote", "level"
+        ]);
+        map.insert("backup", vec!["duration", "footnote", "level"]);
+        map.insert("forward", vec!["duration", "footnote", "level", "voice", "staff"]);
+        map.insert("direction", vec![
+            "direction-type", "offset", "footnote", "level", "voice", "staff", "sound",
+            "listening"
+        ]);
+        map.insert("barline", vec![
+            "bar-style", "footnote", "level", "wavy-line", "segno", "coda", "fermata",
+            "ending", "repeat"
+        ]);
+        map
+    };
+}
+
+/// Position of child_name among the children of parent_name. Children that the
+/// table does not know about go last
+pub fn child_rank(parent_name: &str, child_name: &str) -> usize {
+    CHILD_ORDER.get(parent_name)
+        .and_then(|order| order
+            .iter()
+            .position(|rank| rank.split('|').any(|name| name == child_name))
+        )
+        .unwrap_or(usize::MAX)
+}
+
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_order_note() {
+        let mut note = XmlTag::new("note");
+        for name in ["lyric", "voice", "type", "duration", "pitch", "chord"].iter() {
+            note.direct_add_tag(XmlTag::new(name));
+        }
+        note.order_by_schema();
+        let names: Vec<&str> = note.child_tags.iter()
+            .map(|tag| tag.name.local_name.as_str())
+            .collect();
+        assert_eq!(names, vec!["chord", "pitch", "duration", "voice", "type", "lyric"]);
+    }
+
+    #[test]
+    fn test_comment_follows_next_tag() {
+        let mut note = XmlTag::new("note");
+        note.direct_add_tag(XmlTag::new("duration"));
+        note.direct_add_tag(XmlTag::new_comment(" middle C "));
+        note.direct_add_tag(XmlTag::new("pitch"));
+        note.direct_add_tag(XmlTag::new_comment(" end of note "));
+        note.order_by_schema();
+        let names: Vec<&str> = note.child_tags.iter()
+            .map(|tag| tag.name.local_name.as_str())
+            .collect();
+        assert_eq!(names, vec!["#comment", "pitch", "duration", "#comment"]);
+    }
+
+    #[test]
+    fn test_shared_rank_keeps_groups() {
+        let mut time = XmlTag::new("time");
+        for name in ["beats", "beat-type", "beats", "beat-type"].iter() {
+            time.direct_add_tag(XmlTag::new(name));
+        }
+        let mut expected = time.clone();
+        time.order_by_schema();
+        assert_eq!(time, expected);
+
+        // children of <measure> are a choice and keep their order
+        let mut measure = XmlTag::new("measure");
+        for name in ["note", "backup", "attributes", "note"].iter() {
+            measure.direct_add_tag(XmlTag::new(name));
+        }
+        expected = measure.clone();
+        measure.order_by_schema();
+        assert_eq!(measure, expected);
+    }
+}
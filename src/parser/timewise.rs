@@ -0,0 +1,103 @@
+//! Conversion between the two MusicXML layouts, doing what the official
+//! parttime.xsl and timepart.xsl stylesheets do.
+//! score-partwise nests <measure> inside <part>, score-timewise nests <part> inside
+//! <measure>. The score header and the attributes of <part>/<measure> are kept.
+//! Measures are matched by position, so both layouts need the same number of
+//! measures in every part.
+use crate::parser::xml_tag::XmlTag;
+use crate::libs::error::{MashError, MashResult, Location};
+
+/// Turn <score-partwise> into <score-timewise>
+pub fn partwise_to_timewise(score: &XmlTag) -> MashResult<XmlTag> {
+    transpose(score, "score-partwise", "score-timewise", "part", "measure")
+}
+
+/// Turn <score-timewise> into <score-partwise>
+pub fn timewise_to_partwise(score: &XmlTag) -> MashResult<XmlTag> {
+    transpose(score, "score-timewise", "score-partwise", "measure", "part")
+}
+
+/// Swap the outer and inner level of the score. outer_name is the level directly
+/// under the root in from_root, inner_name the one below it.
+/// The first outer tag decides how many inner tags there are and which attributes
+/// they get, as in the XSLTs.
+fn transpose(score: &XmlTag,
+             from_root: &str,
+             to_root: &str,
+             outer_name: &str,
+             inner_name: &str) -> MashResult<XmlTag>
+{
+    if score.name.local_name != from_root {
+        return Err(MashError::UnexpectedTag {
+            expected: from_root.to_string(), location: Location::of(score)
+        });
+    }
+    let mut transposed = XmlTag::new(to_root);
+    transposed.add_attributes(&score.attributes);
+
+    let outer_tags: Vec< &XmlTag> = score.child_tags
+        .iter()
+        .filter(|tag| tag.name.local_name == outer_name)
+        .collect();
+    // header: everything but the outer level
+    for tag in score.child_tags.iter().filter(|tag| tag.name.local_name != outer_name) {
+        transposed.direct_add_tag(tag.clone());
+    }
+
+    if outer_tags.is_empty() {
+        return Ok(transposed);
+    }
+    let inner_tags: Vec< Vec< &XmlTag>> = outer_tags
+        .iter()
+        .map(|outer| outer.child_tags
+            .iter()
+            .filter(|tag| tag.name.local_name == inner_name)
+            .collect()
+        )
+        .collect();
+
+    for (nth, first_inner) in inner_tags[0].iter().enumerate() {
+        // e.g. <measure number="1" width="...">
+        let mut new_outer = XmlTag::new(inner_name);
+        new_outer.add_attributes(&first_inner.attributes);
+        for (outer, inners) in outer_tags.iter().zip(inner_tags.iter()) {
+            let inner = inners.get(nth).ok_or_else(|| MashError::MissingTag {
+                location: Location::of_path(outer, &format!("{}[{}]", inner_name, nth + 1))
+            }.within(score))?;
+            // e.g. <part id="P1"> holding the content of measure nth of P1
+            let mut new_inner = XmlTag::new(outer_name);
+            new_inner.add_attributes(&outer.attributes);
+            new_inner.child_tags = inner.child_tags.clone();
+            new_outer.direct_add_tag(new_inner);
+        }
+        transposed.direct_add_tag(new_outer);
+    }
+    Ok(transposed)
+}
+
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_round_trip() {
+        let partwise = XmlTag::from_file("src/parser/test/example6.musicxml");
+        let timewise = partwise_to_timewise(&partwise).unwrap();
+
+        assert_eq!(timewise.name.local_name, "score-timewise");
+        assert_eq!(timewise.search_path("measure").len(), 6);
+        assert_eq!(timewise.search_path("measure/part").len(), 12);
+        assert_eq!(timewise.search_tags("note").len(), partwise.search_tags("note").len());
+        let second_part = timewise.child_tags.iter()
+            .find(|tag| tag.name.local_name == "measure").unwrap()
+            .child_tags.back().unwrap();
+        assert_eq!(second_part.get_attribute_value("id").unwrap(), "P2");
+
+        assert_eq!(timewise_to_partwise(&timewise).unwrap(), partwise);
+    }
+
+    #[test]
+    fn test_wrong_root() {
+        let partwise = XmlTag::from_file("src/parser/test/example6.musicxml");
+        assert!(timewise_to_partwise(&partwise).is_err());
+    }
+}
@@ -0,0 +1,409 @@
+//! Arena-backed document tree. Every tag lives in one Vec and is addressed by a
+//! NodeId, a plain index that is Copy and can be stored anywhere without borrowing
+//! the tree. Each node knows its parent and its children by index, so parent and
+//! sibling navigation and indexed child access are O(1), where XmlTag only goes
+//! down through LinkedLists.
+//! XmlArena reads the same documents as XmlTag, .mxl included, and converts to and
+//! from it, see XmlTag::to_arena. It is a separate tree for callers that navigate
+//! upwards or sideways: XmlTag and the music model in msc do not use it, so edits
+//! made here only reach them through XmlArena::to_xml_tag.
+use std::io::Read;
+use std::fs::File;
+use std::path::Path;
+use std::ops::{Index, IndexMut};
+use xml::{EventReader, ParserConfig};
+use xml::common::{Position, TextPosition};
+use xml::name::OwnedName;
+use xml::attribute::OwnedAttribute;
+use xml::reader::XmlEvent;
+use crate::parser::xml_tag::XmlTag;
+use crate::parser::mxl;
+use crate::libs::error::{MashError, MashResult};
+
+/// Handle to a node of an XmlArena. Only meaningful for the arena that made it
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct NodeId(usize);
+
+#[derive(Debug, Clone)]
+pub struct XmlNode {
+    pub name: OwnedName,
+    pub attributes: Vec<OwnedAttribute>,
+    pub text: Option<String>,
+    pub position: Option<TextPosition>,
+
+    parent: Option<NodeId>,
+    children: Vec< NodeId>,
+    index: usize, // position among the children of parent
+}
+
+#[derive(Debug, Clone)]
+pub struct XmlArena {
+    nodes: Vec< XmlNode>,
+    root: NodeId,
+}
+
+impl XmlArena {
+    // Init methods
+    /// Read a .musicxml/.xml file, or an .mxl archive when the extension says so
+    pub fn try_from_file<T: AsRef<Path>> (path: T) -> MashResult<Self>
+    {
+        let xml_file = File::open(&path)?;
+        if mxl::is_mxl_path(&path) {
+            return mxl::read_mxl(xml_file).map(|xml_tag| Self::from_xml_tag(&xml_tag));
+        }
+        Self::try_from_buffer(xml_file)
+    }
+
+    /// Read a document straight into the arena, comments included as in XmlDocument
+    pub fn try_from_buffer(buffer: impl Read) -> MashResult<Self>
+    {
+        let reader = ParserConfig::new()
+            .ignore_comments(false)
+            .create_reader(buffer);
+        Self::try_from_event_reader(reader)
+    }
+
+    pub fn try_from_event_reader(mut reader: EventReader<impl Read>) -> MashResult<Self>
+    {
+        let mut arena = XmlArena { nodes: Vec::with_capacity(1024), root: NodeId(0) };
+        // ids of the open tags
+        let mut stack: Vec< NodeId> = Vec::with_capacity(15);
+        loop {
+            let tag = match reader.next()? {
+                XmlEvent::StartElement { name, attributes, .. } => {
+                    let id = arena.push_node(stack.last().copied(), XmlNode {
+                        name, attributes,
+                        text: None,
+                        position: Some(reader.position()),
+                        parent: None, children: Vec::new(), index: 0
+                    });
+                    stack.push(id);
+                    continue;
+                }
+                XmlEvent::EndElement { .. } => {
+                    stack.pop();
+                    // the first root tag is the document, as for XmlTag
+                    if stack.is_empty() {
+                        return Ok(arena);
+                    }
+                    continue;
+                }
+                XmlEvent::Characters(text) => {
+                    // text split by a comment arrives in pieces
+                    if let Some(&id) = stack.last() {
+                        match arena[id].text.as_mut() {
+                            Some(before) => before.push_str(&text),
+                            None => arena[id].text = Some(text)
+                        }
+                    }
+                    continue;
+                }
+                XmlEvent::Comment(text) => XmlTag::new_comment(&text),
+                XmlEvent::ProcessingInstruction { name, data } =>
+                    XmlTag::new_processing_instruction(&name, data.as_deref()),
+                XmlEvent::EndDocument => return Err(MashError::UnexpectedEof),
+                _ => continue
+            };
+            // comments and processing instructions outside the root are not kept
+            if let Some(&parent) = stack.last() {
+                let id = arena.push_node(Some(parent), Self::leaf_node(tag));
+                arena[id].position = Some(reader.position());
+            }
+        }
+    }
+
+    /// Copy of an XmlTag tree
+    pub fn from_xml_tag(xml_tag: &XmlTag) -> Self {
+        let mut arena = XmlArena { nodes: Vec::with_capacity(1024), root: NodeId(0) };
+        fn _recursive_copy(arena: &mut XmlArena, parent: Option<NodeId>, xml_tag: &XmlTag) {
+            let id = arena.push_node(parent, XmlNode {
+                name: xml_tag.name.clone(),
+                attributes: xml_tag.attributes.clone(),
+                text: xml_tag.text.clone(),
+                position: xml_tag.position,
+                parent: None, children: Vec::new(), index: 0
+            });
+            for child_tag in xml_tag.child_tags.iter() {
+                _recursive_copy(arena, Some(id), child_tag);
+            }
+        }
+        _recursive_copy(&mut arena, None, xml_tag);
+        arena
+    }
+
+    /// The subtree at id as an XmlTag tree
+    pub fn to_xml_tag(&self, id: NodeId) -> XmlTag {
+        let node = &self[id];
+        let mut xml_tag = XmlTag {
+            name: node.name.clone(),
+            attributes: node.attributes.clone(),
+            text: node.text.clone(),
+            position: node.position,
+            child_tags: Default::default()
+        };
+        for &child in node.children.iter() {
+            xml_tag.child_tags.push_back(self.to_xml_tag(child));
+        }
+        xml_tag
+    }
+
+    fn leaf_node(xml_tag: XmlTag) -> XmlNode {
+        XmlNode {
+            name: xml_tag.name,
+            attributes: xml_tag.attributes,
+            text: xml_tag.text,
+            position: xml_tag.position,
+            parent: None, children: Vec::new(), index: 0
+        }
+    }
+
+    /// Store node as the last child of parent, or as the root if there is none
+    fn push_node(&mut self, parent: Option<NodeId>, mut node: XmlNode) -> NodeId {
+        let id = NodeId(self.nodes.len());
+        node.parent = parent;
+        match parent {
+            Some(parent) => {
+                node.index = self[parent].children.len();
+                self[parent].children.push(id);
+            }
+            None => self.root = id
+        }
+        self.nodes.push(node);
+        id
+    }
+
+    // Navigation methods
+    pub fn root(&self) -> NodeId {
+        self.root
+    }
+
+    /// Number of nodes, including any detached ones
+    pub fn len(&self) -> usize {
+        self.nodes.len()
+    }
+
+    /// Never true for an arena read from a document, which has at least its root
+    pub fn is_empty(&self) -> bool {
+        self.nodes.is_empty()
+    }
+
+    pub fn parent(&self, id: NodeId) -> Option<NodeId> {
+        self[id].parent
+    }
+
+    pub fn children(&self, id: NodeId) -> &[NodeId] {
+        &self[id].children
+    }
+
+    /// The nth child of id, counted from 0
+    pub fn nth_child(&self, id: NodeId, n: usize) -> Option<NodeId> {
+        self[id].children.get(n).copied()
+    }
+
+    pub fn next_sibling(&self, id: NodeId) -> Option<NodeId> {
+        let node = &self[id];
+        node.parent.and_then(|parent| self.nth_child(parent, node.index + 1))
+    }
+
+    pub fn prev_sibling(&self, id: NodeId) -> Option<NodeId> {
+        let node = &self[id];
+        match (node.parent, node.index) {
+            (Some(parent), index) if index > 0 => self.nth_child(parent, index - 1),
+            _ => None
+        }
+    }
+
+    /// Parent, grandparent and so on up to the root
+    pub fn ancestors(&self, id: NodeId) -> impl Iterator<Item = NodeId> + '_ {
+        std::iter::successors(self.parent(id), move |&id| self.parent(id))
+    }
+
+    /// id and everything below it in document order
+    pub fn descendants(&self, id: NodeId) -> impl Iterator<Item = NodeId> + '_ {
+        let mut pending = vec![id];
+        std::iter::from_fn(move || {
+            let id = pending.pop()?;
+            pending.extend(self[id].children.iter().rev());
+            Some(id)
+        })
+    }
+
+    /// Element names from the root down to id, e.g. "score-partwise/part/measure"
+    pub fn path_of(&self, id: NodeId) -> String {
+        let mut names: Vec< &str> = self.ancestors(id)
+            .map(|ancestor| self[ancestor].name.local_name.as_str())
+            .collect();
+        names.reverse();
+        names.push(&self[id].name.local_name);
+        names.join("/")
+    }
+
+    // Probing methods, with the same meaning as on XmlTag
+    /// id and its descendants named tag_name
+    pub fn search_tags(&self, id: NodeId, tag_name: &str) -> Vec< NodeId> {
+        self.descendants(id)
+            .filter(|&found| self[found].name.local_name == tag_name)
+            .collect()
+    }
+
+    /// Same as XmlTag::search_path: every name is searched recursively below the
+    /// tags the previous names lead to
+    pub fn search_path(&self, id: NodeId, path: &str) -> Vec< NodeId> {
+        path.split('/').fold(vec![id], |found, tag_name| {
+            found.into_iter()
+                .flat_map(|id| self.search_tags(id, tag_name))
+                .collect()
+        })
+    }
+
+    pub fn get_attribute_value(&self, id: NodeId, attr_name: &str) -> Option<&str> {
+        self[id].attributes
+            .iter()
+            .find(|attr| attr.name.local_name == attr_name)
+            .map(|attr| attr.value.as_str())
+    }
+
+    // Edit methods
+    /// Add a new tag named name as the last child of parent
+    pub fn add_child(&mut self, parent: NodeId, name: &str) -> NodeId {
+        self.push_node(Some(parent), Self::leaf_node(XmlTag::new(name)))
+    }
+
+    /// Unlink id from its parent. The node and its subtree stay in the arena, so
+    /// other NodeIds remain valid, but they are no longer reachable from the root
+    pub fn detach(&mut self, id: NodeId) {
+        let (parent, index) = match self[id].parent {
+            Some(parent) => (parent, self[id].index),
+            None => return
+        };
+        self[parent].children.remove(index);
+        for shifted in index..self[parent].children.len() {
+            let sibling = self[parent].children[shifted];
+            self[sibling].index = shifted;
+        }
+        self[id].parent = None;
+    }
+}
+
+impl Index<NodeId> for XmlArena {
+    type Output = XmlNode;
+
+    fn index(&self, id: NodeId) -> &XmlNode {
+        &self.nodes[id.0]
+    }
+}
+
+impl IndexMut<NodeId> for XmlArena {
+    fn index_mut(&mut self, id: NodeId) -> &mut XmlNode {
+        &mut self.nodes[id.0]
+    }
+}
+
+mod tests {
+    use super::*;
+    use std::time::Instant;
+
+    #[test]
+    fn test_navigation() {
+        let arena = XmlArena::try_from_file("src/parser/test/example6.musicxml").unwrap();
+        let measures = arena.search_path(arena.root(), "part/measure");
+        assert_eq!(measures.len(), 12);
+
+        let second = measures[1];
+        assert_eq!(arena.path_of(second), "score-partwise/part/measure");
+        assert_eq!(arena.prev_sibling(second), Some(measures[0]));
+        assert_eq!(arena.next_sibling(second), Some(measures[2]));
+        assert_eq!(arena.parent(second), arena.parent(measures[0]));
+        let first_child = arena.nth_child(second, 0).unwrap();
+        assert_eq!(arena.prev_sibling(first_child), None);
+        assert_eq!(arena.ancestors(first_child).last(), Some(arena.root()));
+    }
+
+    #[test]
+    fn test_xml_tag_round_trip() {
+        let xml_tree = XmlTag::from_file("src/parser/test/example6.musicxml");
+        let arena = XmlArena::try_from_file("src/parser/test/example6.musicxml").unwrap();
+        assert_eq!(arena.to_xml_tag(arena.root()), xml_tree);
+        let copied = xml_tree.to_arena();
+        assert_eq!(copied.len(), arena.len());
+        assert_eq!(copied.to_xml_tag(copied.root()), xml_tree);
+
+        let path = std::env::temp_dir().join("mash_test_arena.mxl");
+        xml_tree.write_to_file(&path).unwrap();
+        let mxl_arena = XmlArena::try_from_file(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(mxl_arena.to_xml_tag(mxl_arena.root()), xml_tree);
+    }
+
+    #[test]
+    fn test_edit() {
+        let mut arena = XmlArena::try_from_file("src/parser/test/example6.musicxml").unwrap();
+        let measures = arena.search_path(arena.root(), "part/measure");
+        let part = arena.parent(measures[0]).unwrap();
+        arena.detach(measures[1]);
+        assert_eq!(arena.next_sibling(measures[0]), Some(measures[2]));
+        assert_eq!(arena.prev_sibling(measures[2]), Some(measures[0]));
+        assert_eq!(arena.search_path(part, "measure").len(), 5);
+
+        let barline = arena.add_child(measures[0], "barline");
+        arena[barline].attributes.push(OwnedAttribute {
+            name: "location".parse().unwrap(), value: "right".to_string()
+        });
+        assert_eq!(arena.get_attribute_value(arena.children(measures[0]).last().copied().unwrap(), "location"),
+                   Some("right"));
+    }
+
+    /// Runs the same traversal on both trees: for every note of example6, in
+    /// document order, the number of the measure holding it and the name of the
+    /// middle child of that measure. The notes are searched for before timing. The
+    /// arena takes the parent of the note; XmlTag has no parent links and has to
+    /// look for the measure among all of them. Both must give the same answer, and
+    /// the arena must be faster. Ignored by default since debug timings say little:
+    /// run with cargo test --release -- --ignored
+    #[test]
+    #[ignore]
+    fn bench_against_xml_tag() {
+        const ROUNDS: u32 = 100;
+        let source = std::fs::read("src/parser/test/example6.musicxml").unwrap();
+
+        let xml_tree = XmlTag::from_buffer(source.as_slice());
+        let measures = xml_tree.search_path("part/measure");
+        let notes = xml_tree.search_path("part/measure/note");
+        let start = Instant::now();
+        let mut xml_tag_found = Vec::new();
+        for _ in 0..ROUNDS {
+            xml_tag_found = notes
+                .iter()
+                .map(|note| {
+                    let measure = measures.iter()
+                        .find(|measure| measure.child_tags.iter().any(|child| std::ptr::eq(child, *note)))
+                        .unwrap();
+                    let middle = measure.child_tags.iter().nth(measure.child_tags.len() / 2).unwrap();
+                    (measure.get_attribute_value("number").unwrap(), middle.name.local_name.clone())
+                })
+                .collect();
+        }
+        let xml_tag_walk = start.elapsed();
+
+        let arena = XmlArena::try_from_buffer(source.as_slice()).unwrap();
+        let notes = arena.search_path(arena.root(), "part/measure/note");
+        let start = Instant::now();
+        let mut arena_found = Vec::new();
+        for _ in 0..ROUNDS {
+            arena_found = notes
+                .iter()
+                .map(|&note| {
+                    let measure = arena.parent(note).unwrap();
+                    let middle = arena.nth_child(measure, arena.children(measure).len() / 2).unwrap();
+                    (arena.get_attribute_value(measure, "number").unwrap().to_string(),
+                     arena[middle].name.local_name.clone())
+                })
+                .collect();
+        }
+        let arena_walk = start.elapsed();
+
+        assert!(!arena_found.is_empty());
+        assert_eq!(arena_found, xml_tag_found);
+        assert!(arena_walk < xml_tag_walk, "XmlArena {:?}, XmlTag {:?}", arena_walk, xml_tag_walk);
+    }
+}
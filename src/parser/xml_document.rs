@@ -0,0 +1,317 @@
+//! A whole XML file rather than just its root tag: the XML declaration, the DOCTYPE
+//! and the comments and processing instructions around the root. Reading and writing
+//! through XmlDocument keeps all of them where they were, so a rewritten score diffs
+//! cleanly against the file it came from.
+//!
+//! The music model in msc rebuilds most elements from its fields. It puts comments
+//! back in front of the element they came before inside <measure>, <note>, <lyric>,
+//! <key>, <time> and <staff-details>, see XmlTag::anchored_comments. Comments inside
+//! other modeled elements, e.g. <pitch> or <clef>, are not kept, nor are the ones
+//! directly inside an <attributes> holding only modeled children.
+use std::io::{Read, Write};
+use std::fs::File;
+use std::path::Path;
+use std::collections::LinkedList;
+use xml::{EventReader, EventWriter, EmitterConfig, ParserConfig};
+use xml::common::{Position, XmlVersion};
+use xml::reader::XmlEvent;
+use crate::parser::xml_tag::{XmlTag, DOCTYPE_NAME};
+use crate::parser::mxl;
+use crate::libs::error::{MashError, MashResult};
+
+// MusicXML version written in the DOCTYPE when the root does not say
+const DEFAULT_MUSICXML_VERSION: &str = "4.0";
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct XmlDocument {
+    // <?xml version="1.0" encoding="UTF-8" standalone="no"?>
+    pub version: XmlVersion,
+    pub encoding: String,
+    pub standalone: Option<bool>,
+
+    // DOCTYPE, comments and processing instructions before the root, in order
+    pub prolog: Vec< XmlTag>,
+    pub root: XmlTag,
+    // comments and processing instructions after the root
+    pub epilog: Vec< XmlTag>,
+}
+
+impl XmlDocument {
+    /// A document for root as we would write it from scratch: UTF-8 and, for
+    /// <score-partwise> and <score-timewise>, the MusicXML DOCTYPE of root's version
+    pub fn new(root: XmlTag) -> Self {
+        let mut prolog = Vec::new();
+        if let Some(doctype) = musicxml_doctype(&root) {
+            prolog.push(XmlTag::new_doctype(&doctype));
+        }
+        XmlDocument {
+            version: XmlVersion::Version10,
+            encoding: "UTF-8".to_string(),
+            standalone: None,
+            prolog,
+            root,
+            epilog: Vec::new()
+        }
+    }
+
+    /// This document's prolog and epilog around another root, e.g. a score written
+    /// back from the music model
+    pub fn with_root(self, root: XmlTag) -> Self {
+        XmlDocument { root, ..self }
+    }
+
+    /// The DOCTYPE declaration, e.g.
+    /// <!DOCTYPE score-partwise PUBLIC "-//Recordare//DTD MusicXML 3.1 Partwise//EN" "...">
+    pub fn doctype(&self) -> Option<&str> {
+        self.prolog
+            .iter()
+            .find(|tag| tag.name.local_name == DOCTYPE_NAME)
+            .and_then(|tag| tag.text.as_deref())
+    }
+
+    /// The first quoted string of the DOCTYPE, e.g. "-//Recordare//DTD MusicXML 3.1 Partwise//EN"
+    pub fn public_id(&self) -> Option<&str> {
+        self.doctype()
+            .and_then(|doctype| doctype.split('"').nth(1))
+    }
+
+    // Read methods
+    /// Read a .musicxml/.xml file, or an .mxl archive when the extension says so
+    pub fn try_from_file<T: AsRef<Path>> (path: T) -> MashResult<Self>
+    {
+        let xml_file = File::open(&path)?;
+        if mxl::is_mxl_path(&path) {
+            return mxl::read_mxl_document(xml_file);
+        }
+        Self::try_from_buffer(xml_file)
+    }
+
+    /// Read a document, keeping comments
+    pub fn try_from_buffer(buffer: impl Read) -> MashResult<Self>
+    {
+        let event_reader = ParserConfig::new()
+            .ignore_comments(false)
+            .create_reader(buffer);
+        Self::try_from_event_reader(event_reader)
+    }
+
+    pub fn try_from_event_reader(mut reader: EventReader<impl Read>) -> MashResult<Self>
+    {
+        // Same stack walk as XmlTag used to do on its own: a StartElement pushes a tag,
+        // the matching EndElement pops it into its parent. Comments and processing
+        // instructions become children of the tag on top of the stack, or go to the
+        // prolog/epilog when the stack is empty.
+        let mut document = XmlDocument::new(XmlTag::new(""));
+        document.prolog.clear();
+        let mut stack: Vec< XmlTag> = Vec::with_capacity(15);
+        let mut root: Option<XmlTag> = None;
+        // xml-rs has no DOCTYPE event. Prolog nodes seen before it are counted so it
+        // can be put back in between once the root tag makes it available
+        let mut before_doctype = 0;
+        loop {
+            let node = match reader.next()? {
+                XmlEvent::StartDocument { version, encoding, standalone } => {
+                    document.version = version;
+                    document.encoding = encoding;
+                    document.standalone = standalone;
+                    continue;
+                }
+
+                XmlEvent::StartElement { name, attributes, .. } => {
+                    // a fragment with several top level tags: keep the first one only
+                    if let Some(root) = root {
+                        document.root = root;
+                        return Ok(document);
+                    }
+                    if stack.is_empty() {
+                        if let Some(doctype) = reader.doctype() {
+                            document.prolog.insert(before_doctype, XmlTag::new_doctype(doctype));
+                        }
+                    }
+                    stack.push(XmlTag {
+                        name, attributes,
+                        text: None,
+                        position: Some(reader.position()),
+                        child_tags: LinkedList::new()
+                    });
+                    continue;
+                }
+
+                XmlEvent::EndElement { .. } => {
+                    // xml-rs already rejects unbalanced tags
+                    let begin_tag = stack.pop().ok_or(MashError::UnexpectedEof)?;
+                    if let Some(parent) = stack.last_mut() {
+                        parent.child_tags.push_back(begin_tag);
+                    } else {
+                        root = Some(begin_tag);
+                    }
+                    continue;
+                }
+
+                XmlEvent::Characters(text) => {
+                    // text split by a comment arrives in pieces
+                    if let Some(last) = stack.last_mut() {
+                        match last.text.as_mut() {
+                            Some(before) => before.push_str(&text),
+                            None => last.text = Some(text)
+                        }
+                    }
+                    continue;
+                }
+
+                XmlEvent::Comment(text) => XmlTag::new_comment(&text),
+
+                XmlEvent::ProcessingInstruction { name, data } =>
+                    XmlTag::new_processing_instruction(&name, data.as_deref()),
+
+                XmlEvent::EndDocument => {
+                    document.root = root.ok_or(MashError::UnexpectedEof)?;
+                    return Ok(document);
+                }
+
+                _ => continue
+            };
+            let node = XmlTag { position: Some(reader.position()), ..node };
+            if let Some(parent) = stack.last_mut() {
+                parent.child_tags.push_back(node);
+            } else if root.is_some() {
+                document.epilog.push(node);
+            } else {
+                if reader.doctype().is_none() {
+                    before_doctype += 1;
+                }
+                document.prolog.push(node);
+            }
+        }
+    }
+
+    // Write methods
+    /// Write the declaration, prolog, root and epilog to buffer
+    pub fn write_to_buffer<B: Write>(
+        &self,
+        mut buffer: &mut B
+    ) -> MashResult<()> {
+        let mut event_writer: EventWriter<&mut B> = EmitterConfig::new()
+            .perform_indent(true)
+            .autopad_comments(false)
+            .create_writer(&mut buffer);
+
+        event_writer.write(xml::writer::events::XmlEvent::StartDocument {
+            version: self.version,
+            encoding: Some(&self.encoding),
+            standalone: self.standalone
+        })?;
+        for node in self.prolog.iter().chain(std::iter::once(&self.root)).chain(self.epilog.iter()) {
+            XmlTag::write_to_event_writer(node, &mut event_writer)?;
+        }
+        Ok(())
+    }
+
+    /// Write to the file at path, packaged as .mxl when the extension says so
+    pub fn write_to_file<T: AsRef<Path>>(
+        &self,
+        path: T
+    ) -> MashResult<()>
+    {
+        let mut file = File::create(&path)?;
+        if mxl::is_mxl_path(&path) {
+            return mxl::write_mxl_document(self, file);
+        }
+        self.write_to_buffer(&mut file)
+    }
+}
+
+/// <!DOCTYPE ...> the MusicXML spec gives for root, None if root is not a score
+fn musicxml_doctype(root: &XmlTag) -> Option<String> {
+    let (layout, dtd) = match root.name.local_name.as_str() {
+        "score-partwise" => ("Partwise", "partwise.dtd"),
+        "score-timewise" => ("Timewise", "timewise.dtd"),
+        _ => return None
+    };
+    let version = root.get_attribute_value("version")
+        .unwrap_or_else(|| DEFAULT_MUSICXML_VERSION.to_string());
+    Some(format!(
+        "<!DOCTYPE {} PUBLIC \"-//Recordare//DTD MusicXML {} {}//EN\" \"http://www.musicxml.org/dtds/{}\">",
+        root.name.local_name, version, layout, dtd
+    ))
+}
+
+mod tests {
+    use super::*;
+
+    fn xml_commented() -> &'static [u8] {
+        r#"<?xml version="1.0" encoding="UTF-8" standalone="no"?>
+<!-- before the doctype -->
+<!DOCTYPE score-partwise PUBLIC "-//Recordare//DTD MusicXML 3.1 Partwise//EN" "http://www.musicxml.org/dtds/partwise.dtd">
+<?editor keep-layout?>
+<score-partwise version="3.1">
+  <!-- header -->
+  <part-list>
+    <score-part id="P1">
+      <part-name>Piano</part-name>
+    </score-part>
+  </part-list>
+  <part id="P1">
+    <measure number="1">
+      <note>
+        <!-- middle C -->
+        <pitch>
+          <step>C</step>
+          <octave>4</octave>
+        </pitch>
+        <duration>1</duration>
+      </note>
+    </measure>
+  </part>
+</score-partwise>
+<!-- trailing -->
+"#.as_bytes()
+    }
+
+    #[test]
+    fn test_read() {
+        let document = XmlDocument::try_from_buffer(xml_commented()).unwrap();
+        assert_eq!(document.standalone, Some(false));
+        assert_eq!(document.public_id(), Some("-//Recordare//DTD MusicXML 3.1 Partwise//EN"));
+        let prolog: Vec< &str> = document.prolog.iter()
+            .map(|tag| tag.name.local_name.as_str())
+            .collect();
+        assert_eq!(prolog, vec!["#comment", "#doctype", "#processing-instruction"]);
+        assert_eq!(document.epilog[0].text.as_deref(), Some(" trailing "));
+
+        // comments inside the root stay where they were
+        assert_eq!(document.root.child_tags.front().unwrap().text.as_deref(), Some(" header "));
+        let note = document.root.search_path_unique("part/measure/note").unwrap();
+        assert!(!note.child_tags.front().unwrap().is_element());
+        assert_eq!(note.get_tag_content("pitch/step"), Some("C".to_string()));
+    }
+
+    #[test]
+    fn test_round_trip() {
+        let document = XmlDocument::try_from_buffer(xml_commented()).unwrap();
+        let mut buffer = Vec::new();
+        document.write_to_buffer(&mut buffer).unwrap();
+        let written = String::from_utf8(buffer.clone()).unwrap();
+        assert!(written.starts_with(
+            "<?xml version=\"1.0\" encoding=\"UTF-8\" standalone=\"no\"?>\n\
+             <!-- before the doctype -->\n\
+             <!DOCTYPE score-partwise PUBLIC \"-//Recordare//DTD MusicXML 3.1 Partwise//EN\""
+        ));
+        assert_eq!(XmlDocument::try_from_buffer(buffer.as_slice()).unwrap(), document);
+    }
+
+    #[test]
+    fn test_split_text() {
+        let document = XmlDocument::try_from_buffer("<b>12<!--x-->34</b>".as_bytes()).unwrap();
+        assert_eq!(document.root.text.as_deref(), Some("1234"));
+        assert_eq!(document.root.child_tags.front().unwrap().text.as_deref(), Some("x"));
+    }
+
+    #[test]
+    fn test_new() {
+        let score = XmlTag::from_file("src/parser/test/example6.musicxml");
+        let read = XmlDocument::try_from_file("src/parser/test/example6.musicxml").unwrap();
+        assert_eq!(XmlDocument::new(score).doctype(), read.doctype());
+        assert_eq!(XmlDocument::new(XmlTag::new("container")).doctype(), None);
+    }
+}
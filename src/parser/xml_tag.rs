@@ -17,74 +17,95 @@ use std::str::FromStr;
 use std::ops::Deref;
 use std::hint::unreachable_unchecked;
 use std::collections::linked_list::Iter;
+use crate::parser::schema_order::child_rank;
+use crate::parser::xml_document::XmlDocument;
+use crate::parser::xml_arena::XmlArena;
+use crate::parser::query::Query;
+use crate::libs::error::{MashError, MashResult, Location};
+use xml::common::{Position, TextPosition};
+use std::hash::{Hash, Hasher};
 
 #[derive(Debug, Clone)]
 pub struct XmlTag {
     pub name: OwnedName, // tag name
     pub attributes: Vec<OwnedAttribute>,
     pub text: Option<String>, // content between tag pair
+    pub position: Option<TextPosition>, // where the tag starts in the source, None for built tags
 
     pub child_tags: LinkedList< XmlTag>
 }
 
+// Comments, processing instructions and the DOCTYPE are kept in the tree as tags
+// with these names. '#' cannot start an element name, so searches never match them
+pub const COMMENT_NAME: &str = "#comment";
+pub const PROCESSING_INSTRUCTION_NAME: &str = "#processing-instruction";
+pub const DOCTYPE_NAME: &str = "#doctype";
+// Attributes of a comment taken out by anchored_comments: the name of the element
+// child it came before and how many of that name came earlier. Comments are
+// written without their attributes
+const ANCHOR_NAME_ATTRIBUTE: &str = "#before";
+const ANCHOR_NTH_ATTRIBUTE: &str = "#nth";
+
+// Two tags are equal when their content is, wherever they were read from
+impl PartialEq for XmlTag {
+    fn eq(&self, other: &Self) -> bool {
+        self.name == other.name
+            && self.attributes == other.attributes
+            && self.text == other.text
+            && self.child_tags == other.child_tags
+    }
+}
+
+impl Eq for XmlTag {}
+
+impl Hash for XmlTag {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.name.hash(state);
+        self.attributes.hash(state);
+        self.text.hash(state);
+        self.child_tags.hash(state);
+    }
+}
+
 // Core methods implementation
 impl XmlTag {
     // Init methods
     pub fn from_file<T: AsRef<Path>> (path: T) -> Self
     {
-        let xml_file = File::open(path);
-        XmlTag::from_buffer(xml_file.unwrap())
+        Self::try_from_file(path).unwrap()
+    }
+
+    /// Read a .musicxml/.xml file, or an .mxl archive when the extension says so
+    pub fn try_from_file<T: AsRef<Path>> (path: T) -> MashResult<Self>
+    {
+        XmlDocument::try_from_file(path).map(|document| document.root)
     }
 
     pub fn from_buffer(buffer: impl Read) -> Self
     {
-        let event_reader = EventReader::new(buffer);
-        Self::from_event_reader(event_reader)
+        Self::try_from_buffer(buffer).unwrap()
     }
 
-    pub fn from_event_reader(reader: EventReader<impl Read>) -> Self
+    pub fn try_from_buffer(buffer: impl Read) -> MashResult<Self>
     {
-        // Uses stack instead of recursion. When encounter a beginTag, push new XmlTag
-        // onto the stack. If an endTag event encountered, pop the beginTag from stack
-        // then wrap it around an Rc::new() add it to the .child_tags field of
-        // the parent tag.
-        let mut stack: Vec< XmlTag> = Vec::with_capacity(15);
-        for xml_event in reader {
-            match xml_event {
-                Ok(XmlEvent::StartElement {name, attributes, namespace})
-                => {
-                    stack.push(XmlTag {
-                            name, attributes,
-                            text: None,
-                            child_tags: LinkedList::new()
-                        }
-                    );
-                }
+        XmlDocument::try_from_buffer(buffer).map(|document| document.root)
+    }
 
-                Ok(XmlEvent::EndElement {name, ..})
-                => {
-                    // pop the begin_tag that pairs with the current EndElement event
-                    let begin_tag = stack.pop().unwrap();
-                    assert_eq!(begin_tag.name, name);
-                    // if stack is not empty then add child XmlTag, which is begin_tag
-                    // if stack is empty, parse is complete
-                    if let Some(parent) = stack.last_mut() {
-                        parent.child_tags.push_back(begin_tag);
-                    } else {
-                        return begin_tag
-                    }
-                }
+    pub fn from_event_reader(reader: EventReader<impl Read>) -> Self
+    {
+        Self::try_from_event_reader(reader).unwrap()
+    }
 
-                Ok(XmlEvent::Characters (d))
-                => {
-                    let last = stack.last_mut().unwrap();
-                    last.text = Some(d);
-                }
+    /// Read the root tag. Comments inside it are only kept if reader was configured
+    /// not to ignore them, see XmlDocument::try_from_buffer
+    pub fn try_from_event_reader(reader: EventReader<impl Read>) -> MashResult<Self>
+    {
+        XmlDocument::try_from_event_reader(reader).map(|document| document.root)
+    }
 
-                _ => {}
-            }
-        }
-        unreachable!()
+    /// Copy of self with parent links and indexed children, see XmlArena
+    pub fn to_arena(&self) -> XmlArena {
+        XmlArena::from_xml_tag(self)
     }
 
     // Probing methods
@@ -108,15 +129,24 @@ impl XmlTag {
         return results;
     }
 
-    /// This method is used when there are multiple <tag> present.
-    /// It will return the nth tag
-    pub fn get_nth_tag(
+    /// Tags selected by query, e.g. r#"clef[@number="2"]/sign"# or "measure[3]".
+    /// See parser::query. Compile a Query once and use select_with instead when
+    /// running it on many tags
+    pub fn select(
         &self,
-        path: &str,
-        n: u8
-    ) -> &XmlTag
+        query: &str
+    ) -> MashResult<Vec< &XmlTag>>
     {
-        self.search_path(path).split_off((n - 1) as usize).pop_front().unwrap()
+        Ok(self.select_with(&Query::new(query)?))
+    }
+
+    /// Tags selected by a compiled query, see Query::new
+    pub fn select_with(
+        &self,
+        query: &Query
+    ) -> Vec< &XmlTag>
+    {
+        query.select(self)
     }
 
     /// Return the count of a certain tag. Fn takes path to tag
@@ -189,18 +219,45 @@ impl XmlTag {
         res.pop_front()
     }
 
+    /// Like search_path_unique but a missing tag is an error
+    pub fn require_tag(
+        &self,
+        path: &str
+    ) -> MashResult<&XmlTag>
+    {
+        self.search_path_unique(path)
+            .ok_or_else(|| MashError::MissingTag { location: Location::of_path(self, path) })
+    }
+
+    /// Get the text between tag pair of self
+    pub fn require_text(&self) -> MashResult<&str>
+    {
+        self.text
+            .as_deref()
+            .ok_or_else(|| MashError::MissingText { location: Location::of(self) })
+    }
+
     /// Get the text between tag pair
     pub fn get_tag_content(
         self: &XmlTag,
         tag_path: &str
     ) -> Option<String>
     {
-        let mut res = self.search_path(tag_path);
-        if res.is_empty() {
-            return None;
+        self.try_get_tag_content(tag_path).unwrap()
+    }
+
+    /// Get the text between tag pair. A tag without text is an error
+    pub fn try_get_tag_content(
+        self: &XmlTag,
+        tag_path: &str
+    ) -> MashResult<Option<String>>
+    {
+        match self.search_path_unique(tag_path) {
+            Some(tag) => tag.require_text()
+                .map(|text| Some(text.to_string()))
+                .map_err(|_| MashError::MissingText { location: Location::of_path(self, tag_path) }),
+            None => Ok(None)
         }
-        Some(res.pop_front().unwrap()
-            .text.clone().unwrap())
     }
 
     /// Search for tag at tag_path. Cast the string content to type T and return
@@ -211,13 +268,36 @@ impl XmlTag {
     where T: FromStr,
           <T as std::str::FromStr>::Err: std::fmt::Debug
     {
-        let mut res = self.search_path(tag_path);
-        if res.is_empty() {
-            return None;
+        self.try_get_tag_content_as(tag_path).unwrap()
+    }
+
+    /// Fallible get_tag_content_as. A missing tag is Ok(None), content that does not
+    /// parse as T is an error
+    pub fn try_get_tag_content_as<T>(
+        self: &XmlTag,
+        tag_path: &str
+    ) -> MashResult<Option<T>>
+    where T: FromStr
+    {
+        match self.try_get_tag_content(tag_path)? {
+            Some(text) => text.trim().parse()
+                .map(|value| Some(value))
+                .map_err(|_| MashError::InvalidValue {
+                    value: text, location: Location::of_path(self, tag_path)
+                }),
+            None => Ok(None)
         }
-        Some(res.pop_front().unwrap()
-            .text.clone().unwrap()
-            .parse().unwrap())
+    }
+
+    /// Like try_get_tag_content_as but a missing tag is an error
+    pub fn require_tag_content_as<T>(
+        self: &XmlTag,
+        tag_path: &str
+    ) -> MashResult<T>
+    where T: FromStr
+    {
+        self.try_get_tag_content_as(tag_path)?
+            .ok_or_else(|| MashError::MissingTag { location: Location::of_path(self, tag_path) })
     }
 
     /// Get attribute as string
@@ -232,6 +312,18 @@ impl XmlTag {
             .and_then(|attr| Some(attr.value.clone()))
     }
 
+    /// Like get_attribute_value but a missing attribute is an error
+    pub fn require_attribute_value (
+        &self,
+        attr_name: &str
+    ) -> MashResult<String>
+    {
+        self.get_attribute_value(attr_name)
+            .ok_or_else(|| MashError::MissingAttribute {
+                name: attr_name.to_string(), location: Location::of(self)
+            })
+    }
+
     /// Get attribute with name attr_name then cast
     pub fn get_attribute_as <T> (
         self: &XmlTag,
@@ -246,62 +338,136 @@ impl XmlTag {
 
     }
 
+    /// Fallible get_attribute_as. A missing attribute is Ok(None), a value that
+    /// does not parse as T is an error
+    pub fn try_get_attribute_as <T> (
+        self: &XmlTag,
+        attr_name: &str
+    ) -> MashResult<Option<T>>
+    where T: FromStr
+    {
+        match self.get_attribute_value(attr_name) {
+            Some(value) => value.parse()
+                .map(|parsed| Some(parsed))
+                .map_err(|_| MashError::InvalidValue {
+                    location: Location::of_path(self, &format!("@{}", attr_name)), value
+                }),
+            None => Ok(None)
+        }
+    }
+
     /// Push child tags with name in tag_names in self to extra_tags: mut Vec<...>
     /// This method is used to push tags that we dont care about into a storage
-    /// that can be taken out when we need to write object to file
+    /// that can be taken out when we need to write object to file.
+    /// Only direct children are pushed, a tag of the same name deeper down
+    /// (e.g. <identification> inside <score-part>) is written with its parent
     pub fn push_extra_tags_to<'a>(
         self: &'a XmlTag,
         tag_names: &[&str],
         sink: &mut Vec< &'a XmlTag>,
     ) {
         for tag_name in tag_names {
-            self.search_tags(tag_name)
+            self.child_tags
                 .iter()
+                .filter(|tag| tag.name.local_name == *tag_name)
                 .for_each(|tag| sink.push(tag))
         }
     }
 
     // Builder methods
-    pub fn new_tag_builder() -> Self {
+    /// Create a lone tag with no attributes, text or children
+    pub fn new(name: &str) -> Self {
         Self {
             name: OwnedName {
-                local_name: "tag_builder_begin".to_string(),
+                local_name: name.to_string(),
                 namespace: None,
                 prefix: None
             },
-            attributes: vec![],
+            attributes: Vec::with_capacity(5),
             text: None,
+            position: None,
             child_tags: LinkedList::new()
         }
     }
 
+    /// <!--text-->
+    pub fn new_comment(text: &str) -> Self {
+        let mut comment = Self::new(COMMENT_NAME);
+        comment.text = Some(text.to_string());
+        comment
+    }
+
+    /// <?target data?>. The target is kept as an attribute of the same name
+    pub fn new_processing_instruction(target: &str, data: Option<&str>) -> Self {
+        let mut instruction = Self::new(PROCESSING_INSTRUCTION_NAME);
+        instruction.add_attribute("target", target);
+        instruction.text = data.map(|data| data.to_string());
+        instruction
+    }
+
+    /// A whole <!DOCTYPE ...> declaration, written back verbatim
+    pub fn new_doctype(declaration: &str) -> Self {
+        let mut doctype = Self::new(DOCTYPE_NAME);
+        doctype.text = Some(declaration.to_string());
+        doctype
+    }
+
+    /// False for comments, processing instructions and the DOCTYPE
+    pub fn is_element(&self) -> bool {
+        !self.name.local_name.starts_with('#')
+    }
+
+    pub fn new_tag_builder() -> Self {
+        Self::new("tag_builder_begin")
+    }
+
+    /// Take the tag built last. Its children are put in MusicXML schema order
     pub fn built_tag(&mut self) -> Self {
-        self.child_tags.pop_back().unwrap()
+        let mut tag = self.child_tags.pop_back().unwrap();
+        tag.order_by_schema();
+        tag
+    }
+
+    /// Recursively sort child tags into the order the MusicXML schema requires.
+    /// See parser::schema_order
+    pub fn order_by_schema(&mut self) {
+        let parent_name = self.name.local_name.as_str();
+        let children: Vec< XmlTag> = std::mem::take(&mut self.child_tags)
+            .into_iter()
+            .collect();
+        // comments and processing instructions stay in front of the element after them
+        let mut ranks = vec![usize::MAX; children.len()];
+        let mut next_rank = usize::MAX;
+        for (nth, child) in children.iter().enumerate().rev() {
+            if child.is_element() {
+                next_rank = child_rank(parent_name, &child.name.local_name);
+            }
+            ranks[nth] = next_rank;
+        }
+        // stable sort: children of equal rank keep the order they were added in
+        let mut ranked: Vec< (usize, XmlTag)> = ranks.into_iter().zip(children).collect();
+        ranked.sort_by_key(|(rank, _)| *rank);
+        let mut children: Vec< XmlTag> = ranked.into_iter().map(|(_, child)| child).collect();
+        for child in children.iter_mut() {
+            child.order_by_schema();
+        }
+        self.child_tags = children.into_iter().collect();
     }
 
-    /// Add a tag as child of self
+    /// Add a tag as child of self. If a child with that name exists, the last one
+    /// is returned instead so chained calls add to it
     pub fn add_tag(&mut self, name: &str) -> &mut Self {
-        if self.child_tags
+        let exists = self.child_tags
+            .iter()
+            .any(|child_tag| child_tag.name.local_name == name);
+        if !exists {
+            self.child_tags.push_back(XmlTag::new(name));
+        }
+        self.child_tags
             .iter_mut()
+            .rev()
             .find(|child_tag| child_tag.name.local_name == name)
-            .is_some()
-        {
-            self.child_tags.back_mut().unwrap()
-        } else {
-            // If tag not already exists in child_tags, create tag
-            let new_tag = XmlTag {
-                name: OwnedName {
-                    local_name: name.to_string(),
-                    namespace: None,
-                    prefix: None
-                },
-                attributes: Vec::with_capacity(5),
-                text: None,
-                child_tags: LinkedList::new()
-            };
-            self.child_tags.push_back(new_tag);
-            self.child_tags.back_mut().unwrap()
-        }
+            .unwrap()
     }
 
     /// Directly add XmlTag other as a child of self
@@ -351,6 +517,205 @@ impl XmlTag {
         self.text = Some(text.as_ref().to_string())
     }
 
+    // Edit methods
+    /// Mutable search_path. Calls edit on every tag search_path would return, in the
+    /// same order, and returns how many there were
+    pub fn search_path_mut(
+        &mut self,
+        path: &str,
+        mut edit: impl FnMut(&mut XmlTag)
+    ) -> usize
+    {
+        fn _recursive_search(xml_tag: &mut XmlTag,
+                             tag_names: &[&str],
+                             edit: &mut dyn FnMut(&mut XmlTag))
+        {
+            match tag_names.split_first() {
+                None => edit(xml_tag),
+                Some((tag_name, rest)) => xml_tag.search_tags_mut(
+                    tag_name, &mut |found| _recursive_search(found, rest, edit)
+                )
+            }
+        }
+        let tag_names: Vec< &str> = path.split('/').collect();
+        let mut count = 0;
+        _recursive_search(self, &tag_names, &mut |tag| {
+            count += 1;
+            edit(tag)
+        });
+        count
+    }
+
+    /// Mutable search_tags: calls edit on self and every descendant named tag_name
+    fn search_tags_mut(&mut self, tag_name: &str, edit: &mut dyn FnMut(&mut XmlTag)) {
+        if self.name.local_name == tag_name {
+            edit(self);
+        }
+        for child_tag in self.child_tags.iter_mut() {
+            child_tag.search_tags_mut(tag_name, edit);
+        }
+    }
+
+    /// Call edit on self and then on every descendant, parents before children
+    pub fn walk_mut(&mut self, edit: &mut impl FnMut(&mut XmlTag)) {
+        edit(self);
+        for child_tag in self.child_tags.iter_mut() {
+            child_tag.walk_mut(edit);
+        }
+    }
+
+    /// Remove every tag search_path(path) would return, except self.
+    /// Returns the removed tags in document order
+    pub fn remove_path(&mut self, path: &str) -> Vec< XmlTag> {
+        // the last name is searched recursively below each tag the rest leads to
+        let (parent_path, tag_name) = match path.rfind('/') {
+            Some(split) => (Some(&path[..split]), &path[split + 1..]),
+            None => (None, path)
+        };
+        fn _remove_descendants(xml_tag: &mut XmlTag, tag_name: &str, removed: &mut Vec< XmlTag>) {
+            let mut kept = LinkedList::new();
+            while let Some(mut child_tag) = xml_tag.child_tags.pop_front() {
+                if child_tag.name.local_name == tag_name {
+                    removed.push(child_tag);
+                } else {
+                    _remove_descendants(&mut child_tag, tag_name, removed);
+                    kept.push_back(child_tag);
+                }
+            }
+            xml_tag.child_tags = kept;
+        }
+        let mut removed = Vec::new();
+        match parent_path {
+            Some(parent_path) => {
+                self.search_path_mut(parent_path, |parent| _remove_descendants(parent, tag_name, &mut removed));
+            }
+            None => _remove_descendants(self, tag_name, &mut removed)
+        }
+        removed
+    }
+
+    /// Put other where self is and return what self was
+    pub fn replace_with(&mut self, other: XmlTag) -> XmlTag {
+        std::mem::replace(self, other)
+    }
+
+    /// Insert other before the first child named child_name.
+    /// Returns false and leaves self unchanged if there is no such child
+    pub fn insert_before(&mut self, child_name: &str, other: XmlTag) -> bool {
+        match self.child_position(child_name) {
+            Some(index) => {
+                self.insert_child(index, other);
+                true
+            }
+            None => false
+        }
+    }
+
+    /// Insert other after the last child named child_name.
+    /// Returns false and leaves self unchanged if there is no such child
+    pub fn insert_after(&mut self, child_name: &str, other: XmlTag) -> bool {
+        let last = self.child_tags
+            .iter()
+            .rposition(|child_tag| child_tag.name.local_name == child_name);
+        match last {
+            Some(index) => {
+                self.insert_child(index + 1, other);
+                true
+            }
+            None => false
+        }
+    }
+
+    fn child_position(&self, child_name: &str) -> Option<usize> {
+        self.child_tags
+            .iter()
+            .position(|child_tag| child_tag.name.local_name == child_name)
+    }
+
+    fn insert_child(&mut self, index: usize, other: XmlTag) {
+        let mut tail = self.child_tags.split_off(index);
+        self.child_tags.push_back(other);
+        self.child_tags.append(&mut tail);
+    }
+
+    /// Keep only the direct children for which keep returns true
+    pub fn retain_children(&mut self, mut keep: impl FnMut(&XmlTag) -> bool) {
+        self.child_tags = std::mem::take(&mut self.child_tags)
+            .into_iter()
+            .filter(|child_tag| keep(child_tag))
+            .collect();
+    }
+
+    /// Comments and processing instructions among the children of self, each marked
+    /// with the element child it comes before, e.g. the second <beam>. Models that
+    /// write self back from their fields keep these and hand them to restore_comments
+    pub fn anchored_comments(&self) -> Vec< XmlTag> {
+        let mut comments = Vec::new();
+        let mut pending = Vec::new();
+        let mut seen: HashMap<&str, usize> = HashMap::new();
+        for child_tag in self.child_tags.iter() {
+            if !child_tag.is_element() {
+                pending.push(child_tag.clone());
+                continue;
+            }
+            let nth = seen.entry(child_tag.name.local_name.as_str()).or_default();
+            for mut comment in pending.drain(..) {
+                comment.set_attribute(ANCHOR_NAME_ATTRIBUTE, &child_tag.name.local_name);
+                comment.set_attribute(ANCHOR_NTH_ATTRIBUTE, nth.to_string());
+                comments.push(comment);
+            }
+            *nth += 1;
+        }
+        comments.append(&mut pending);
+        comments
+    }
+
+    /// Put comments from anchored_comments back in front of the element child they
+    /// came before, in order. They go last when that element is gone
+    pub fn restore_comments<'a>(&mut self, comments: impl IntoIterator<Item = &'a XmlTag>) {
+        let mut children: Vec< XmlTag> = std::mem::take(&mut self.child_tags).into_iter().collect();
+        for comment in comments {
+            let anchor = comment.get_attribute_value(ANCHOR_NAME_ATTRIBUTE);
+            let nth: usize = comment.get_attribute_value(ANCHOR_NTH_ATTRIBUTE)
+                .and_then(|nth| nth.parse().ok())
+                .unwrap_or_default();
+            let position = children.iter()
+                .enumerate()
+                .filter(|(_, child)| Some(&child.name.local_name) == anchor.as_ref())
+                .nth(nth)
+                .map_or(children.len(), |(position, _)| position);
+            children.insert(position, comment.clone());
+        }
+        self.child_tags = children.into_iter().collect();
+    }
+
+    /// Change the tag name, keeping attributes, text and children
+    pub fn rename(&mut self, name: &str) -> &mut Self {
+        self.name.local_name = name.to_string();
+        self
+    }
+
+    /// Set attribute key to value, adding it if missing
+    pub fn set_attribute(&mut self,
+                         key: impl AsRef<str>,
+                         value: impl AsRef<str>)
+        -> &mut Self
+    {
+        match self.attributes.iter_mut().find(|attr| attr.name.local_name == key.as_ref()) {
+            Some(attr) => attr.value = value.as_ref().to_string(),
+            None => { self.add_attribute(key, value); }
+        }
+        self
+    }
+
+    /// Remove attribute key, returning its value
+    pub fn remove_attribute(&mut self, key: &str) -> Option<String> {
+        let index = self.attributes
+            .iter()
+            .position(|attr| attr.name.local_name == key)?;
+        Some(self.attributes.remove(index).value)
+    }
+
     /// Recursively travels down self and compare self.name.local_name with
     /// other.name.local_name. If matched, merge their children together
     /// Function returns a bool indicating success or failure
@@ -376,38 +741,73 @@ impl XmlTag {
     pub fn write_to_event_writer<W>(
         xml_tag: &XmlTag,
         writer: &mut EventWriter<W>
-    )
+    ) -> MashResult<()>
     where W: Write
     {
+        match xml_tag.name.local_name.as_str() {
+            COMMENT_NAME => {
+                writer.write(xml::writer::events::XmlEvent::comment(
+                    xml_tag.text.as_deref().unwrap_or("")
+                ))?;
+                return Ok(());
+            }
+            PROCESSING_INSTRUCTION_NAME => {
+                let target = xml_tag.get_attribute_value("target").unwrap_or_default();
+                writer.write(xml::writer::events::XmlEvent::processing_instruction(
+                    target.as_str(), xml_tag.text.as_deref()
+                ))?;
+                return Ok(());
+            }
+            DOCTYPE_NAME => {
+                // xml-rs has no DOCTYPE event, the declaration goes straight to the output
+                if let Some(declaration) = xml_tag.text.as_ref() {
+                    write!(writer.inner_mut(), "\n{}", declaration)?;
+                }
+                return Ok(());
+            }
+            _ => {}
+        }
         let mut start_event =
             xml::writer::events::XmlEvent::start_element(xml_tag.name.local_name.as_ref());
         for attr in xml_tag.attributes.iter() {
             start_event = start_event.attr(attr.name.local_name.as_ref(), attr.value.as_ref());
         }
-        writer.write(start_event);
+        writer.write(start_event)?;
         // if text exists, write characters event
         if xml_tag.text.is_some() {
             let text_event = xml::writer::events::XmlEvent::characters(xml_tag.text.as_ref().unwrap());
-            writer.write(text_event);
+            writer.write(text_event)?;
         }
         // recursively writes children
         for child_tag in xml_tag.child_tags.iter() {
-            Self::write_to_event_writer(child_tag, writer);
+            Self::write_to_event_writer(child_tag, writer)?;
         }
         let end_event = xml::writer::events::XmlEvent::end_element();
-        writer.write(end_event);
+        writer.write(end_event)?;
+        Ok(())
     }
 
     /// Write self to buffer given
     pub fn write_to_buffer<B: Write>(
         &self,
         mut buffer: &mut B
-    ) {
+    ) -> MashResult<()> {
         let mut event_writer = EmitterConfig::new()
             .perform_indent(true)
+            .autopad_comments(false)
             .create_writer(&mut buffer);
 
-        Self::write_to_event_writer(&self, &mut event_writer);
+        Self::write_to_event_writer(self, &mut event_writer)
+    }
+
+    /// Write self to the file at path, packaged as .mxl when the extension says so.
+    /// The file gets an XML declaration and, for scores, the MusicXML DOCTYPE
+    pub fn write_to_file<T: AsRef<Path>>(
+        &self,
+        path: T
+    ) -> MashResult<()>
+    {
+        XmlDocument::new(self.clone()).write_to_file(path)
     }
 
     // Debug methods
@@ -535,7 +935,43 @@ mod tests {
         let xml_tree = XmlTag::from_file("src/parser/test/example6.musicxml");
         let mut file = File::create("src/parser/test/test_xml_write.musicxml").unwrap();
         let partial = XmlTag::search_tags(&xml_tree, "measure");
-        partial.front().unwrap().write_to_buffer(&mut file);
+        partial.front().unwrap().write_to_buffer(&mut file).unwrap();
+    }
+
+    #[test]
+    fn test_write_error() {
+        // a buffer that takes no more than a few bytes
+        struct Full(usize);
+        impl Write for Full {
+            fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+                if self.0 < buf.len() {
+                    return Err(std::io::Error::new(std::io::ErrorKind::WriteZero, "buffer full"));
+                }
+                self.0 -= buf.len();
+                Ok(buf.len())
+            }
+            fn flush(&mut self) -> std::io::Result<()> { Ok(()) }
+        }
+        let xml_tree = XmlTag::from_file("src/parser/test/example6.musicxml");
+        match xml_tree.write_to_buffer(&mut Full(64)) {
+            Err(MashError::Io(err)) => assert_eq!(err.kind(), std::io::ErrorKind::WriteZero),
+            other => panic!("unexpected result {:?}", other)
+        }
+        assert!(XmlTag::new("note").write_to_file("/missing/dir/note.xml").is_err());
+    }
+
+    #[test]
+    fn test_malformed() {
+        let err = XmlTag::try_from_buffer("<note>\n  <pitch></note>".as_bytes()).unwrap_err();
+        match err {
+            MashError::Xml { position, .. } => assert_eq!(position.row, 1),
+            _ => panic!("unexpected error {:?}", err)
+        }
+        match XmlTag::try_from_buffer("<note>".as_bytes()) {
+            Err(MashError::UnexpectedEof) | Err(MashError::Xml { .. }) => {}
+            other => panic!("unexpected result {:?}", other)
+        }
+        assert!(XmlTag::try_from_file("src/parser/test/missing.musicxml").is_err());
     }
 
     #[test]
@@ -546,6 +982,75 @@ mod tests {
         xml_tree.print_debug(0);
     }
 
+    #[test]
+    fn test_edit() {
+        let mut xml_tree = XmlTag::from_file("src/parser/test/example6.musicxml");
+        // strip layout positions everywhere
+        xml_tree.walk_mut(&mut |tag| { tag.remove_attribute("default-x"); });
+        assert!(xml_tree.search_tags("credit-words").front().unwrap()
+            .get_attribute_value("default-x").is_none());
+        // rewrite every program
+        let programs = xml_tree.search_path_mut("part-list/score-part/midi-program", |tag| tag.add_text("41"));
+        assert_eq!(programs, 2);
+        assert_eq!(xml_tree.get_tag_content("part-list/score-part/midi-program"), Some("41".to_string()));
+
+        let notes = xml_tree.search_tags("note").len();
+        let stems = xml_tree.remove_path("part/measure/note/stem");
+        assert!(!stems.is_empty() && stems.len() <= notes);
+        assert!(!xml_tree.path_exists("stem"));
+
+        let measure = xml_tree.search_path_unique("part/measure").unwrap().clone();
+        let mut first_note = measure.search_path_unique("note").unwrap().clone();
+        first_note.rename("forward").retain_children(|tag| tag.name.local_name == "duration");
+        first_note.set_attribute("print-object", "no");
+        let mut measure = measure;
+        assert!(measure.insert_before("note", first_note.clone()));
+        assert!(measure.insert_after("note", XmlTag::new("barline")));
+        assert!(!measure.insert_before("harmony", XmlTag::new("barline")));
+        let names: Vec< &str> = measure.child_tags.iter()
+            .map(|tag| tag.name.local_name.as_str())
+            .filter(|name| *name != "print" && *name != "attributes")
+            .collect();
+        assert_eq!(names.first(), Some(&"forward"));
+        assert_eq!(names.last(), Some(&"barline"));
+
+        let old = measure.replace_with(XmlTag::new("measure"));
+        assert_eq!(old.child_tags.front().unwrap().name.local_name, "print");
+        assert!(measure.child_tags.is_empty());
+    }
+
+    #[test]
+    fn test_anchored_comments() {
+        let note = XmlTag::from_buffer("<note><beam>begin</beam><!--a--><beam>begin</beam><stem>up</stem><!--b--></note>"
+            .as_bytes());
+        let comments = note.anchored_comments();
+        // rebuilt in another order, without the second <beam>
+        let mut built = XmlTag::new("note");
+        built.direct_add_tag(note.child_tags.iter().nth(3).unwrap().clone());
+        built.direct_add_tag(note.child_tags.front().unwrap().clone());
+        let mut restored = built.clone();
+        restored.restore_comments(comments.iter());
+        let names: Vec< &str> = restored.child_tags.iter().map(|tag| tag.name.local_name.as_str()).collect();
+        assert_eq!(names, vec!["stem", "beam", "#comment", "#comment"]);
+
+        built.direct_add_tag(note.child_tags.iter().nth(2).unwrap().clone());
+        built.restore_comments(comments.iter());
+        let texts: Vec< Option<&str>> = built.child_tags.iter().map(|tag| tag.text.as_deref()).collect();
+        assert_eq!(texts, vec![Some("up"), Some("begin"), Some("a"), Some("begin"), Some("b")]);
+    }
+
+    #[test]
+    fn test_add_tag_existing() {
+        let mut builder = XmlTag::new_tag_builder();
+        builder.add_tag("attributes").add_tag("divisions").add_text("2");
+        builder.add_tag("note");
+        // used to return <note>, the last child, instead of <attributes>
+        builder.add_tag("attributes").add_tag("staves").add_text("1");
+        builder.child_tags.pop_back();
+        let attributes = builder.built_tag();
+        assert_eq!(attributes.get_tag_content("staves"), Some("1".to_string()));
+    }
+
     #[test]
     fn test_builder () {
         let mut builder = XmlTag::new_tag_builder();